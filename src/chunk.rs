@@ -1,12 +1,47 @@
+use crate::error::line_start;
+use crate::object::Object;
 use crate::opcode::OpCode;
+use crate::token::Span;
 use crate::value::{Value, ValueArray};
-use crate::vm::InterpretResult;
+use std::io;
+use std::io::Write;
+use std::rc::Rc;
 
 #[derive(Debug, Clone)]
 pub struct Chunk {
     code: Vec<u8>,
-    lines: Vec<usize>,
+    lines: Vec<LineStart>,
+    /// Token runs encoded one after another, see `TokenStart`.
+    tokens: Vec<u8>,
+    /// The last run in `tokens`, which the next one is encoded against.
+    last_token: TokenStart,
+    /// Byte offset in the source of the start of the last line written.
+    line_start: usize,
     constants: ValueArray,
+    /// The source text the chunk was compiled from, for quoting in errors.
+    source: Rc<str>,
+    /// Locals spelled like the undefined global read or written by the
+    /// instruction at the paired offset, for suggesting in runtime errors.
+    suggestions: Vec<(usize, String)>,
+}
+
+/// Start of a run of code bytes that all came from the same source line.
+#[derive(Debug, Clone)]
+struct LineStart {
+    offset: usize,
+    line: usize,
+}
+
+/// Start of a run of code bytes whose tokens sit at the same place within
+/// their lines, with `start` counted in bytes from the start of the line.
+/// Nearly every instruction starts a run, so each is stored as three varints:
+/// the offset and start as differences from the previous run, the start
+/// zigzag encoded as it can move back, and the length as is.
+#[derive(Debug, Default, Copy, Clone)]
+struct TokenStart {
+    offset: usize,
+    start: usize,
+    len: usize,
 }
 
 impl Chunk {
@@ -14,98 +49,356 @@ impl Chunk {
         Self {
             code: Vec::new(),
             lines: Vec::new(),
+            tokens: Vec::new(),
+            last_token: TokenStart::default(),
+            line_start: 0,
             constants: ValueArray::new(),
+            source: Rc::from(""),
+            suggestions: Vec::new(),
         }
     }
 
-    pub fn write_opcode(&mut self, code: OpCode, line: usize) {
-        self.code.push(code.into());
-        self.lines.push(line);
+    pub fn write_opcode(&mut self, code: OpCode, span: Span) {
+        self.write(code.into(), span);
     }
 
-    pub fn write(&mut self, byte: u8, line: usize) {
+    pub fn write(&mut self, byte: u8, span: Span) {
+        let offset = self.code.len();
         self.code.push(byte);
-        self.lines.push(line);
+        if self
+            .lines
+            .last()
+            .is_none_or(|start| start.line != span.line)
+        {
+            self.lines.push(LineStart {
+                offset,
+                line: span.line,
+            });
+            self.line_start = line_start(&self.source, span.start);
+        }
+
+        let token = TokenStart {
+            offset,
+            start: span.start.saturating_sub(self.line_start),
+            len: span.end - span.start,
+        };
+        let last = self.last_token;
+        if (token.start, token.len) != (last.start, last.len) {
+            write_varint(&mut self.tokens, token.offset - last.offset);
+            write_varint(
+                &mut self.tokens,
+                zigzag(token.start as isize - last.start as isize),
+            );
+            write_varint(&mut self.tokens, token.len);
+            self.last_token = token;
+        }
+    }
+
+    /// Decodes the token runs up to the one containing `offset`. Only needed
+    /// to report errors, so a linear scan is fine.
+    fn get_token(&self, offset: usize) -> TokenStart {
+        let mut bytes = self.tokens.iter().copied();
+        let mut token = TokenStart::default();
+        while let Some(delta) = read_varint(&mut bytes) {
+            let (Some(start), Some(len)) = (read_varint(&mut bytes), read_varint(&mut bytes))
+            else {
+                break;
+            };
+            if token.offset + delta > offset {
+                break;
+            }
+            token = TokenStart {
+                offset: token.offset + delta,
+                start: (token.start as isize + unzigzag(start)) as usize,
+                len,
+            };
+        }
+        token
+    }
+
+    pub fn patch(&mut self, offset: usize, byte: u8) {
+        self.code[offset] = byte;
+    }
+
+    pub fn count(&self) -> usize {
+        self.code.len()
     }
 
     pub fn read(&self, ip: usize) -> u8 {
         self.code[ip]
     }
 
-    pub fn get_line(&self, ip: usize) -> usize {
-        self.lines[ip]
+    pub fn get_line(&self, offset: usize) -> usize {
+        let run = self.lines.partition_point(|start| start.offset <= offset);
+        self.lines[run - 1].line
     }
 
-    pub fn free(&mut self) {
-        self.code.clear();
-        self.lines.clear();
-        self.constants.free();
+    pub fn get_span(&self, offset: usize) -> Span {
+        let line = self.get_line(offset);
+        // Line starts aren't stored, so find the line by counting newlines.
+        let line_start = match line {
+            0 | 1 => 0,
+            _ => self
+                .source
+                .match_indices('\n')
+                .nth(line - 2)
+                .map_or(self.source.len(), |(newline, _)| newline + 1),
+        };
+        let token = self.get_token(offset);
+        let start = line_start + token.start;
+        let column = self
+            .source
+            .get(line_start..start)
+            .map_or(0, |before| before.chars().count());
+        Span {
+            line,
+            column: column + 1,
+            start,
+            end: start + token.len,
+        }
     }
 
-    pub fn add_constant(&mut self, value: Value) -> Option<u8> {
-        let idx = self.constants.write(value);
-        match u8::try_from(idx) {
-            Ok(v) => Some(v),
-            Err(_) => None,
-        }
+    /// Must be set before any code is written, as spans are stored relative
+    /// to the start of their line in `source`.
+    pub fn set_source(&mut self, source: Rc<str>) {
+        self.source = source;
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    pub fn add_suggestion(&mut self, offset: usize, name: String) {
+        self.suggestions.push((offset, name));
+    }
+
+    pub fn get_suggestion(&self, offset: usize) -> Option<&str> {
+        self.suggestions
+            .iter()
+            .find(|(at, _)| *at == offset)
+            .map(|(_, name)| name.as_str())
+    }
+
+    pub fn add_constant(&mut self, value: Value) -> usize {
+        self.constants.write(value)
+    }
+
+    pub fn constants(&self) -> impl Iterator<Item = &Value> {
+        self.constants.iter()
     }
 
-    pub fn get_constant(&self, index: usize) -> Result<Value, InterpretResult> {
+    pub fn get_constant(&self, index: usize) -> Option<Value> {
         self.constants.read_value(index)
     }
 
-    pub fn disassemble<T: ToString>(&mut self, name: T) {
-        println!("== {} ==", name.to_string());
+    pub fn disassemble<T: ToString>(&self, out: &mut dyn Write, name: T) -> io::Result<()> {
+        writeln!(out, "== {} ==", name.to_string())?;
         let mut offset = 0;
         while offset < self.code.len() {
-            offset = self.disassemble_instruction(offset)
+            offset = self.disassemble_instruction(out, offset)?;
         }
+        Ok(())
     }
 
-    pub fn disassemble_instruction(&self, offset: usize) -> usize {
-        print!("{offset:04} ");
+    pub fn disassemble_instruction(&self, out: &mut dyn Write, offset: usize) -> io::Result<usize> {
+        write!(out, "{offset:04} ")?;
 
-        if offset > 0 && self.lines[offset] == self.lines[offset - 1] {
-            print!("    |   ");
+        let line = self.get_line(offset);
+        if offset > 0 && line == self.get_line(offset - 1) {
+            write!(out, "    |   ")?;
         } else {
-            print!("  {:4}  ", self.lines[offset]);
+            write!(out, "  {line:4}  ")?;
         }
         let instruction: OpCode = self.code[offset].into();
         match instruction {
-            OpCode::Return => self.simple_instruction("OP_RETURN", offset),
-            OpCode::Constant => self.constant_instruction("OP_CONSTANT", offset),
-            OpCode::Negate => self.simple_instruction("OP_NEGATE", offset),
-            OpCode::Add => self.simple_instruction("OP_ADD", offset),
-            OpCode::Subtract => self.simple_instruction("OP_SUBTRACT", offset),
-            OpCode::Multiply => self.simple_instruction("OP_MULTIPLY", offset),
-            OpCode::Divide => self.simple_instruction("OP_DIVIDE", offset),
-            OpCode::Nil => self.simple_instruction("OP_NIL", offset),
-            OpCode::True => self.simple_instruction("OP_TRUE", offset),
-            OpCode::False => self.simple_instruction("OP_FALSE", offset),
-            OpCode::Not => self.simple_instruction("OP_NOT", offset),
-            OpCode::Equal => self.simple_instruction("OP_EQUAL", offset),
-            OpCode::Greater => self.simple_instruction("OP_GREATER", offset),
-            OpCode::Less => self.simple_instruction("OP_LESS", offset),
-            OpCode::BangEqual => self.simple_instruction("OP_BANG_EQUAL", offset),
-            OpCode::GreaterEqual => self.simple_instruction("OP_GREATER_EQUAL", offset),
-            OpCode::LessEqual => self.simple_instruction("OP_LESS_EQUAL", offset),
-            OpCode::Print => self.simple_instruction("OP_PRINT", offset),
-            OpCode::Pop => self.simple_instruction("OP_POP", offset),
-            OpCode::DefineGlobal => self.constant_instruction("OP_DEFINE_GLOBAL", offset),
-            OpCode::GetGlobal => self.constant_instruction("OP_GET_GLOBAL", offset),
+            OpCode::Return => self.simple_instruction(out, "OP_RETURN", offset),
+            OpCode::Constant => self.constant_instruction(out, "OP_CONSTANT", offset),
+            OpCode::Negate => self.simple_instruction(out, "OP_NEGATE", offset),
+            OpCode::Add => self.simple_instruction(out, "OP_ADD", offset),
+            OpCode::Subtract => self.simple_instruction(out, "OP_SUBTRACT", offset),
+            OpCode::Multiply => self.simple_instruction(out, "OP_MULTIPLY", offset),
+            OpCode::Divide => self.simple_instruction(out, "OP_DIVIDE", offset),
+            OpCode::Nil => self.simple_instruction(out, "OP_NIL", offset),
+            OpCode::True => self.simple_instruction(out, "OP_TRUE", offset),
+            OpCode::False => self.simple_instruction(out, "OP_FALSE", offset),
+            OpCode::Not => self.simple_instruction(out, "OP_NOT", offset),
+            OpCode::Equal => self.simple_instruction(out, "OP_EQUAL", offset),
+            OpCode::Greater => self.simple_instruction(out, "OP_GREATER", offset),
+            OpCode::Less => self.simple_instruction(out, "OP_LESS", offset),
+            OpCode::BangEqual => self.simple_instruction(out, "OP_BANG_EQUAL", offset),
+            OpCode::GreaterEqual => self.simple_instruction(out, "OP_GREATER_EQUAL", offset),
+            OpCode::LessEqual => self.simple_instruction(out, "OP_LESS_EQUAL", offset),
+            OpCode::Print => self.simple_instruction(out, "OP_PRINT", offset),
+            OpCode::Pop => self.simple_instruction(out, "OP_POP", offset),
+            OpCode::DefineGlobal => self.constant_instruction(out, "OP_DEFINE_GLOBAL", offset),
+            OpCode::GetGlobal => self.constant_instruction(out, "OP_GET_GLOBAL", offset),
+            OpCode::SetGlobal => self.constant_instruction(out, "OP_SET_GLOBAL", offset),
+            OpCode::GetLocal => self.byte_instruction(out, "OP_GET_LOCAL", offset),
+            OpCode::SetLocal => self.byte_instruction(out, "OP_SET_LOCAL", offset),
+            OpCode::Jump => self.jump_instruction(out, "OP_JUMP", 1, offset),
+            OpCode::JumpIfFalse => self.jump_instruction(out, "OP_JUMP_IF_FALSE", 1, offset),
+            OpCode::Loop => self.jump_instruction(out, "OP_LOOP", -1, offset),
+            OpCode::Call => self.byte_instruction(out, "OP_CALL", offset),
+            OpCode::Closure => self.closure_instruction(out, "OP_CLOSURE", offset),
+            OpCode::GetUpvalue => self.byte_instruction(out, "OP_GET_UPVALUE", offset),
+            OpCode::SetUpvalue => self.byte_instruction(out, "OP_SET_UPVALUE", offset),
+            OpCode::CloseUpvalue => self.simple_instruction(out, "OP_CLOSE_UPVALUE", offset),
+            OpCode::Class => self.constant_instruction(out, "OP_CLASS", offset),
+            OpCode::GetProperty => self.constant_instruction(out, "OP_GET_PROPERTY", offset),
+            OpCode::SetProperty => self.constant_instruction(out, "OP_SET_PROPERTY", offset),
+            OpCode::Method => self.constant_instruction(out, "OP_METHOD", offset),
+            OpCode::Invoke => self.invoke_instruction(out, "OP_INVOKE", offset),
+            OpCode::Inherit => self.simple_instruction(out, "OP_INHERIT", offset),
+            OpCode::GetSuper => self.constant_instruction(out, "OP_GET_SUPER", offset),
+            OpCode::SuperInvoke => self.invoke_instruction(out, "OP_SUPER_INVOKE", offset),
+            OpCode::ConstantLong => self.constant_instruction(out, "OP_CONSTANT_LONG", offset),
+            OpCode::DefineGlobalLong => {
+                self.constant_instruction(out, "OP_DEFINE_GLOBAL_LONG", offset)
+            }
+            OpCode::GetGlobalLong => self.constant_instruction(out, "OP_GET_GLOBAL_LONG", offset),
+            OpCode::SetGlobalLong => self.constant_instruction(out, "OP_SET_GLOBAL_LONG", offset),
+            OpCode::ClosureLong => self.closure_instruction(out, "OP_CLOSURE_LONG", offset),
+            OpCode::ClassLong => self.constant_instruction(out, "OP_CLASS_LONG", offset),
+            OpCode::GetPropertyLong => {
+                self.constant_instruction(out, "OP_GET_PROPERTY_LONG", offset)
+            }
+            OpCode::SetPropertyLong => {
+                self.constant_instruction(out, "OP_SET_PROPERTY_LONG", offset)
+            }
+            OpCode::MethodLong => self.constant_instruction(out, "OP_METHOD_LONG", offset),
+            OpCode::InvokeLong => self.invoke_instruction(out, "OP_INVOKE_LONG", offset),
+            OpCode::GetSuperLong => self.constant_instruction(out, "OP_GET_SUPER_LONG", offset),
+            OpCode::SuperInvokeLong => self.invoke_instruction(out, "OP_SUPER_INVOKE_LONG", offset),
+        }
+    }
+
+    pub fn simple_instruction(
+        &self,
+        out: &mut dyn Write,
+        name: &str,
+        offset: usize,
+    ) -> io::Result<usize> {
+        writeln!(out, "{name}")?;
+        Ok(offset + 1)
+    }
+
+    pub fn byte_instruction(
+        &self,
+        out: &mut dyn Write,
+        name: &str,
+        offset: usize,
+    ) -> io::Result<usize> {
+        let slot = self.code[offset + 1];
+        writeln!(out, "{name:-16} {slot:4}")?;
+        Ok(offset + 2)
+    }
+
+    pub fn jump_instruction(
+        &self,
+        out: &mut dyn Write,
+        name: &str,
+        sign: isize,
+        offset: usize,
+    ) -> io::Result<usize> {
+        let jump = ((self.code[offset + 1] as u16) << 8) | self.code[offset + 2] as u16;
+        let target = offset as isize + 3 + sign * jump as isize;
+        writeln!(out, "{name:-16} {offset:4} -> {target}")?;
+        Ok(offset + 3)
+    }
+
+    pub fn invoke_instruction(
+        &self,
+        out: &mut dyn Write,
+        name: &str,
+        offset: usize,
+    ) -> io::Result<usize> {
+        let (constant, offset) = self.constant_operand(offset);
+        let arg_count = self.code[offset];
+        write!(out, "{name:-16} ({arg_count} args) {constant:4} '")?;
+        self.constants.write_value(out, constant)?;
+        writeln!(out, "'")?;
+        Ok(offset + 1)
+    }
+
+    pub fn closure_instruction(
+        &self,
+        out: &mut dyn Write,
+        name: &str,
+        offset: usize,
+    ) -> io::Result<usize> {
+        let (constant, mut offset) = self.constant_operand(offset);
+        write!(out, "{name:-16} {constant:4} ")?;
+        self.constants.write_value(out, constant)?;
+        writeln!(out)?;
+
+        if let Some(Value::Obj(Object::Function(function))) = self.get_constant(constant) {
+            for _ in 0..function.upvalue_count {
+                let is_local = self.code[offset];
+                let index = self.code[offset + 1];
+                writeln!(
+                    out,
+                    "{offset:04}      |                     {} {index}",
+                    if is_local == 1 { "local" } else { "upvalue" }
+                )?;
+                offset += 2;
+            }
         }
+        Ok(offset)
     }
 
-    pub fn simple_instruction(&self, name: &str, offset: usize) -> usize {
-        println!("{name}");
-        offset + 1
+    pub fn constant_instruction(
+        &self,
+        out: &mut dyn Write,
+        name: &str,
+        offset: usize,
+    ) -> io::Result<usize> {
+        let (constant, offset) = self.constant_operand(offset);
+        write!(out, "{name:-16} {constant:4} '")?;
+        self.constants.write_value(out, constant)?;
+        writeln!(out, "'")?;
+        Ok(offset)
     }
 
-    pub fn constant_instruction(&self, name: &str, offset: usize) -> usize {
-        let constant = self.code[offset + 1];
-        print!("{name:-16} {constant:4} '");
-        self.constants.print_value(constant as usize);
-        println!("'");
-        offset + 2
+    /// Decodes the constant index of the instruction at `offset`, returning it
+    /// with the offset just past the operand.
+    fn constant_operand(&self, offset: usize) -> (usize, usize) {
+        if OpCode::from(self.code[offset]).is_long() {
+            let constant = ((self.code[offset + 1] as usize) << 16)
+                | ((self.code[offset + 2] as usize) << 8)
+                | self.code[offset + 3] as usize;
+            (constant, offset + 4)
+        } else {
+            (self.code[offset + 1] as usize, offset + 2)
+        }
     }
 }
+
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(bytes: &mut impl Iterator<Item = u8>) -> Option<usize> {
+    let mut value = 0;
+    let mut shift = 0;
+    for byte in bytes {
+        value |= ((byte & 0x7f) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+        shift += 7;
+    }
+    None
+}
+
+/// Maps small negative and positive numbers alike to small varints.
+fn zigzag(value: isize) -> usize {
+    ((value << 1) ^ (value >> (isize::BITS - 1))) as usize
+}
+
+fn unzigzag(value: usize) -> isize {
+    (value >> 1) as isize ^ -((value & 1) as isize)
+}
@@ -1,50 +1,135 @@
 use crate::chunk::Chunk;
-use crate::object::Object;
+use crate::error::{source_line, Diagnostic, ErrorKind, LoxError, Warning, WarningKind};
+use crate::gc::{Gc, Heap};
+use crate::object::{Function, Object};
 use crate::opcode::OpCode;
 use crate::precedence::Precedence;
 use crate::scanner::Scanner;
-use crate::token::Token;
+use crate::suggest::closest;
+use crate::token::{Span, Token};
 use crate::token_type::TokenType;
 use crate::value::Value;
-use crate::vm::InterpretResult;
 use std::cell::RefCell;
+use std::collections::HashSet;
+use std::io::Write;
+use std::rc::Rc;
 
 pub struct Compiler<'a> {
+    heap: &'a mut Heap,
+    /// Where to disassemble compiled functions, if anywhere.
+    trace: Option<&'a mut dyn Write>,
     parser: Parser,
+    source: Rc<str>,
     scanner: Scanner,
-    chunk: &'a mut Chunk,
+    current: Box<FunctionCompiler>,
+    current_class: Option<Box<ClassCompiler>>,
+    echo: bool,
+    max_errors: Option<usize>,
+    enabled_warnings: HashSet<WarningKind>,
+    /// Calls and assignments compiled so far, to tell expression statements
+    /// that do something from ones that don't.
+    side_effects: usize,
     // rules: Vec<ParseRule<'a>>,
 }
 
 impl<'a> Compiler<'a> {
-    pub fn new(chunk: &'a mut Chunk) -> Self {
+    pub fn new(heap: &'a mut Heap, trace: Option<&'a mut dyn Write>) -> Self {
         // let rules =
         Self {
+            heap,
+            trace,
             parser: Default::default(),
+            source: Rc::from(""),
             scanner: Scanner::new(""),
-            chunk,
+            current: Box::new(FunctionCompiler::new(None, FunctionType::Script)),
+            current_class: None,
+            echo: false,
+            max_errors: Some(DEFAULT_MAX_ERRORS),
+            enabled_warnings: WarningKind::ALL.into_iter().collect(),
+            side_effects: 0,
             // rules,
         }
     }
 
-    pub fn compile(&mut self, source: &str) -> Result<(), InterpretResult> {
-        self.scanner = Scanner::new(source);
+    /// Makes a trailing top-level expression statement with no `;` print its
+    /// value instead of discarding it.
+    pub fn set_echo(&mut self, echo: bool) {
+        self.echo = echo;
+    }
+
+    /// Stops compiling once this many errors have been reported, or never
+    /// when `None`. The first error is always reported.
+    pub fn set_max_errors(&mut self, max_errors: Option<usize>) {
+        self.max_errors = max_errors;
+    }
+
+    pub fn set_warning(&mut self, kind: WarningKind, enabled: bool) {
+        if enabled {
+            self.enabled_warnings.insert(kind);
+        } else {
+            self.enabled_warnings.remove(&kind);
+        }
+    }
+
+    /// Warnings found by the last successful compile, in source order.
+    pub fn take_warnings(&mut self) -> Vec<Warning> {
+        let mut warnings = std::mem::take(&mut self.parser.warnings);
+        warnings.sort_by_key(|warning| warning.diagnostic.span.start);
+        warnings
+    }
+
+    pub fn compile(&mut self, source: &str) -> Result<Gc<Function>, LoxError> {
+        self.set_source(source);
         self.advance();
 
         while !self.is_match(TokenType::Eof) {
             self.declaration();
         }
 
-        self.end_compiler();
-        if *self.parser.had_error.borrow() {
-            Err(InterpretResult::CompileError)
+        self.finish()
+    }
+
+    /// Compiles a single expression into a script that returns its value.
+    pub fn compile_expression(&mut self, source: &str) -> Result<Gc<Function>, LoxError> {
+        self.set_source(source);
+        self.advance();
+
+        self.expression();
+        self.consume(TokenType::Eof, "Expect end of expression.");
+        self.emit_code(OpCode::Return);
+
+        self.finish()
+    }
+
+    fn set_source(&mut self, source: &str) {
+        self.source = Rc::from(source);
+        self.scanner = Scanner::new(source);
+        let source = Rc::clone(&self.source);
+        self.current_chunk().set_source(source);
+    }
+
+    fn finish(&mut self) -> Result<Gc<Function>, LoxError> {
+        let compiler = self.end_compiler();
+        if !self.parser.diagnostics.is_empty() {
+            // Warnings about code that failed to parse are mostly noise.
+            self.parser.warnings.clear();
+            let diagnostics = std::mem::take(&mut self.parser.diagnostics);
+            Err(LoxError::new(ErrorKind::Compile, diagnostics))
         } else {
-            Ok(())
+            Ok(self.heap.alloc(compiler.function))
         }
     }
 
+    fn current_chunk(&mut self) -> &mut Chunk {
+        &mut self.current.function.chunk
+    }
+
     fn declaration(&mut self) {
-        if self.is_match(TokenType::Var) {
+        if self.is_match(TokenType::Class) {
+            self.class_declaration();
+        } else if self.is_match(TokenType::Fun) {
+            self.fun_declaration();
+        } else if self.is_match(TokenType::Var) {
             self.var_declaration();
         } else {
             self.statement();
@@ -54,6 +139,132 @@ impl<'a> Compiler<'a> {
         }
     }
 
+    fn class_declaration(&mut self) {
+        self.consume(TokenType::Identifier, "Expect class name.");
+        let class_name = self.parser.previous.as_string();
+        let name_constant = self.identifier_constant(class_name.clone());
+        self.declare_variable();
+
+        self.emit_indexed(OpCode::Class, name_constant);
+        self.define_variable(name_constant);
+
+        let enclosing = self.current_class.take();
+        self.current_class = Some(Box::new(ClassCompiler {
+            enclosing,
+            has_superclass: false,
+        }));
+
+        if self.is_match(TokenType::Less) {
+            self.consume(TokenType::Identifier, "Expect superclass name.");
+            self.variable(false);
+
+            if class_name == self.parser.previous.lexeme {
+                self.error_at_previous("A class can't inherit from itself.");
+            }
+
+            self.begin_scope();
+            let span = self.parser.previous.span;
+            self.add_local(Token::new(TokenType::Super, "super".to_string(), span));
+            self.mark_used();
+            self.define_variable(0);
+
+            self.name_variable(class_name.clone(), false);
+            self.emit_code(OpCode::Inherit);
+            if let Some(class) = self.current_class.as_mut() {
+                class.has_superclass = true;
+            }
+        }
+
+        self.name_variable(class_name, false);
+        self.consume(TokenType::LeftBrace, "Expect '{' before class body.");
+        self.parser.open_braces += 1;
+        while !self.check(TokenType::RightBrace) && !self.check(TokenType::Eof) {
+            self.method();
+            if *self.parser.panic_mode.borrow() {
+                self.synchronize();
+            }
+        }
+        self.parser.open_braces -= 1;
+        self.consume(TokenType::RightBrace, "Expect '}' after class body.");
+        self.emit_code(OpCode::Pop);
+
+        if let Some(class) = self.current_class.take() {
+            if class.has_superclass {
+                self.end_scope();
+            }
+            self.current_class = class.enclosing;
+        }
+    }
+
+    fn method(&mut self) {
+        if !self.is_match(TokenType::Identifier) {
+            self.error_at_current("Expect method name.");
+            // Skip the bad token so the class body makes progress.
+            self.advance();
+            return;
+        }
+        let name = self.parser.previous.as_string();
+        let constant = self.identifier_constant(name.clone());
+
+        let function_type = if name == "init" {
+            FunctionType::Initializer
+        } else {
+            FunctionType::Method
+        };
+        self.function(function_type);
+        self.emit_indexed(OpCode::Method, constant);
+    }
+
+    fn fun_declaration(&mut self) {
+        let global = self.parse_variable("Expect function name.");
+        self.mark_initialized();
+        self.function(FunctionType::Function);
+        self.define_variable(global);
+    }
+
+    fn function(&mut self, function_type: FunctionType) {
+        let name = self.parser.previous.as_string();
+        let enclosing = std::mem::replace(
+            &mut self.current,
+            Box::new(FunctionCompiler::new(Some(name), function_type)),
+        );
+        self.current.enclosing = Some(enclosing);
+        let source = Rc::clone(&self.source);
+        self.current_chunk().set_source(source);
+        self.begin_scope();
+
+        self.consume(TokenType::LeftParen, "Expect '(' after function name.");
+        if !self.check(TokenType::RightParen) {
+            loop {
+                self.current.function.arity += 1;
+                if self.current.function.arity > 255 {
+                    self.error_at_current("Can't have more than 255 parameters.");
+                }
+                let constant = self.parse_variable("Expect parameter name.");
+                self.define_variable(constant);
+                // Parameters are part of the signature, so may go unread.
+                self.mark_used();
+
+                if !self.is_match(TokenType::Comma) {
+                    break;
+                }
+            }
+        }
+        self.close_paren("Expect ')' after parameters.");
+        self.consume(TokenType::LeftBrace, "Expect '{' before function body.");
+        self.block();
+
+        let compiler = self.end_compiler();
+        let function = self.heap.alloc(compiler.function);
+        let constant = self.make_constant(Value::Obj(Object::Function(function)));
+        self.emit_indexed(OpCode::Closure, constant);
+
+        for upvalue in compiler.upvalues {
+            self.emit_byte(upvalue.is_local as u8);
+            self.emit_byte(upvalue.index);
+        }
+    }
+
     fn var_declaration(&mut self) {
         let golbal = self.parse_variable("Expect variable name.");
 
@@ -71,26 +282,138 @@ impl<'a> Compiler<'a> {
         self.define_variable(golbal);
     }
 
-    fn define_variable(&mut self, global: u8) {
-        self.emit_bytes(OpCode::DefineGlobal, global);
+    fn define_variable(&mut self, global: usize) {
+        if self.current.scope_depth > 0 {
+            self.mark_initialized();
+            return;
+        }
+        self.emit_indexed(OpCode::DefineGlobal, global);
+    }
+
+    fn mark_initialized(&mut self) {
+        if self.current.scope_depth == 0 {
+            return;
+        }
+        if let Some(local) = self.current.locals.last_mut() {
+            local.depth = Some(self.current.scope_depth);
+        }
+    }
+
+    fn declare_variable(&mut self) {
+        if self.current.scope_depth == 0 {
+            return;
+        }
+
+        let name = self.parser.previous.clone();
+        let mut already_declared = false;
+        for local in self.current.locals.iter().rev() {
+            if let Some(depth) = local.depth {
+                if depth < self.current.scope_depth {
+                    break;
+                }
+            }
+            if local.name.lexeme == name.lexeme {
+                already_declared = true;
+                break;
+            }
+        }
+
+        if already_declared {
+            self.error_at_previous("Already a variable with this name in this scope.");
+        } else if self.current.local_names().contains(&name.lexeme.as_str()) {
+            let message = format!(
+                "'{}' shadows a variable in an enclosing scope.",
+                name.lexeme
+            );
+            self.warn_at(WarningKind::Shadowing, name.span, &message);
+        }
+
+        self.add_local(name);
     }
 
-    fn identifier_constant(&mut self, name: String) -> u8 {
+    fn add_local(&mut self, name: Token) {
+        if self.current.locals.len() == UINT8_COUNT {
+            self.error_at_previous("Too many local variables in function.");
+            return;
+        }
+        self.current.locals.push(Local {
+            name,
+            depth: None,
+            is_captured: false,
+            is_used: false,
+        });
+    }
+
+    /// Exempts the most recently declared local from the unused variable
+    /// warning.
+    fn mark_used(&mut self) {
+        if let Some(local) = self.current.locals.last_mut() {
+            local.is_used = true;
+        }
+    }
+
+    fn warn_if_unused(&mut self, local: Local) {
+        if local.is_used || local.name.lexeme.starts_with('_') {
+            return;
+        }
+        let message = format!("Local variable '{}' is never read.", local.name.lexeme);
+        self.warn_at(WarningKind::UnusedVariable, local.name.span, &message);
+    }
+
+    fn resolve_local(&mut self, name: &str) -> Option<u8> {
+        match self.current.resolve_local(name) {
+            Ok(slot) => slot,
+            Err(message) => {
+                self.error_at_previous(message);
+                None
+            }
+        }
+    }
+
+    fn resolve_upvalue(&mut self, name: &str) -> Option<u8> {
+        match self.current.resolve_upvalue(name) {
+            Ok(index) => index,
+            Err(message) => {
+                self.error_at_previous(message);
+                None
+            }
+        }
+    }
+
+    fn identifier_constant(&mut self, name: String) -> usize {
+        let name = self.heap.intern(name);
         self.make_constant(Value::Obj(Object::Str(name)))
     }
 
-    fn parse_variable(&mut self, message: &str) -> u8 {
+    fn parse_variable(&mut self, message: &str) -> usize {
         self.consume(TokenType::Identifier, message);
+
+        self.declare_variable();
+        if self.current.scope_depth > 0 {
+            return 0;
+        }
+
         self.identifier_constant(self.parser.previous.as_string())
     }
 
     fn synchronize(&mut self) {
         self.parser.panic_mode.replace(false);
+        // Braces opened while skipping, whose contents are skipped too.
+        let mut depth: usize = 0;
         while !self.parser.current.is(TokenType::Eof) {
-            if self.parser.previous.is(TokenType::SemiColon) {
+            if depth == 0 && self.parser.previous.is(TokenType::SemiColon) {
                 return;
             }
             match self.parser.current.ttype {
+                TokenType::LeftBrace => depth += 1,
+                // Leave the closing brace to the enclosing block or class body.
+                TokenType::RightBrace if depth == 0 && self.parser.open_braces > 0 => return,
+                TokenType::RightBrace if depth == 1 => {
+                    // A skipped block ends a declaration just as a `;` does.
+                    self.advance();
+                    return;
+                }
+                TokenType::RightBrace => depth = depth.saturating_sub(1),
                 TokenType::Class
                 | TokenType::Fun
                 | TokenType::Var
@@ -98,7 +421,9 @@ impl<'a> Compiler<'a> {
                 | TokenType::If
                 | TokenType::While
                 | TokenType::Print
-                | TokenType::Return => {
+                | TokenType::Return
+                    if depth == 0 =>
+                {
                     return;
                 }
                 _ => {}
@@ -107,12 +432,179 @@ impl<'a> Compiler<'a> {
         }
     }
 
+    /// Consumes the `)` closing a parenthesized list. After an error inside
+    /// the parentheses, skips to the matching `)` and resumes from there, so
+    /// the rest of the statement is still checked.
+    fn close_paren(&mut self, message: &str) {
+        if !self.check(TokenType::RightParen) {
+            self.error_at_current(message);
+            // The bad expression may have consumed the statement's `;` already.
+            if self.parser.previous.is(TokenType::SemiColon) {
+                return;
+            }
+        }
+
+        let mut depth = 0;
+        loop {
+            match self.parser.current.ttype {
+                TokenType::RightParen if depth == 0 => break,
+                TokenType::RightParen => depth -= 1,
+                TokenType::LeftParen => depth += 1,
+                TokenType::SemiColon
+                | TokenType::LeftBrace
+                | TokenType::RightBrace
+                | TokenType::Eof => return,
+                _ => {}
+            }
+            self.advance();
+        }
+        self.parser.panic_mode.replace(false);
+        self.advance();
+    }
+
     fn statement(&mut self) {
         if self.is_match(TokenType::Print) {
             self.print_statement();
+        } else if self.is_match(TokenType::Return) {
+            self.return_statement();
+        } else if self.is_match(TokenType::For) {
+            self.for_statement();
+        } else if self.is_match(TokenType::If) {
+            self.if_statement();
+        } else if self.is_match(TokenType::While) {
+            self.while_statement();
+        } else if self.is_match(TokenType::LeftBrace) {
+            self.begin_scope();
+            self.block();
+            self.end_scope();
+        } else {
+            self.expression_statement();
+        }
+    }
+
+    fn block(&mut self) {
+        self.parser.open_braces += 1;
+        let mut after_return = false;
+        while !self.check(TokenType::RightBrace) && !self.check(TokenType::Eof) {
+            // Warn only at the first of the unreachable statements.
+            if after_return {
+                let span = self.parser.current.span;
+                self.warn_at(
+                    WarningKind::UnreachableCode,
+                    span,
+                    "Unreachable code after 'return'.",
+                );
+            }
+            after_return = self.check(TokenType::Return);
+            self.declaration();
+        }
+        self.parser.open_braces -= 1;
+
+        self.consume(TokenType::RightBrace, "Expect '}' after block.");
+    }
+
+    fn begin_scope(&mut self) {
+        self.current.scope_depth += 1;
+    }
+
+    fn end_scope(&mut self) {
+        self.current.scope_depth -= 1;
+
+        while let Some(local) = self.current.locals.last() {
+            match local.depth {
+                Some(depth) if depth <= self.current.scope_depth => break,
+                _ => {
+                    if local.is_captured {
+                        self.emit_code(OpCode::CloseUpvalue);
+                    } else {
+                        self.emit_code(OpCode::Pop);
+                    }
+                    if let Some(local) = self.current.locals.pop() {
+                        self.warn_if_unused(local);
+                    }
+                }
+            }
+        }
+    }
+
+    fn if_statement(&mut self) {
+        self.consume(TokenType::LeftParen, "Expect '(' after 'if'.");
+        self.expression();
+        self.close_paren("Expect ')' after condition.");
+
+        let then_jump = self.emit_jump(OpCode::JumpIfFalse);
+        self.emit_code(OpCode::Pop);
+        self.statement();
+
+        let else_jump = self.emit_jump(OpCode::Jump);
+
+        self.patch_jump(then_jump);
+        self.emit_code(OpCode::Pop);
+
+        if self.is_match(TokenType::Else) {
+            self.statement();
+        }
+        self.patch_jump(else_jump);
+    }
+
+    fn while_statement(&mut self) {
+        let loop_start = self.current_chunk().count();
+        self.consume(TokenType::LeftParen, "Expect '(' after 'while'.");
+        self.expression();
+        self.close_paren("Expect ')' after condition.");
+
+        let exit_jump = self.emit_jump(OpCode::JumpIfFalse);
+        self.emit_code(OpCode::Pop);
+        self.statement();
+        self.emit_loop(loop_start);
+
+        self.patch_jump(exit_jump);
+        self.emit_code(OpCode::Pop);
+    }
+
+    fn for_statement(&mut self) {
+        self.begin_scope();
+        self.consume(TokenType::LeftParen, "Expect '(' after 'for'.");
+        if self.is_match(TokenType::SemiColon) {
+            // No initializer.
+        } else if self.is_match(TokenType::Var) {
+            self.var_declaration();
         } else {
             self.expression_statement();
         }
+
+        let mut loop_start = self.current_chunk().count();
+        let mut exit_jump = None;
+        if !self.is_match(TokenType::SemiColon) {
+            self.expression();
+            self.consume(TokenType::SemiColon, "Expect ';' after loop condition.");
+
+            // Jump out of the loop if the condition is false.
+            exit_jump = Some(self.emit_jump(OpCode::JumpIfFalse));
+            self.emit_code(OpCode::Pop);
+        }
+
+        if !self.is_match(TokenType::RightParen) {
+            let body_jump = self.emit_jump(OpCode::Jump);
+            let increment_start = self.current_chunk().count();
+            self.expression();
+            self.emit_code(OpCode::Pop);
+            self.consume(TokenType::RightParen, "Expect ')' after for clauses.");
+
+            self.emit_loop(loop_start);
+            loop_start = increment_start;
+            self.patch_jump(body_jump);
+        }
+
+        self.statement();
+        self.emit_loop(loop_start);
+
+        if let Some(exit_jump) = exit_jump {
+            self.patch_jump(exit_jump);
+            self.emit_code(OpCode::Pop);
+        }
+
+        self.end_scope();
     }
 
     fn print_statement(&mut self) {
@@ -121,8 +613,47 @@ impl<'a> Compiler<'a> {
         self.emit_code(OpCode::Print);
     }
 
+    fn return_statement(&mut self) {
+        if self.current.function_type == FunctionType::Script {
+            self.error_at_previous("Can't return from top-level code.");
+        }
+
+        if self.is_match(TokenType::SemiColon) {
+            self.emit_return();
+        } else {
+            if self.current.function_type == FunctionType::Initializer {
+                self.error_at_previous("Can't return a value from an initializer.");
+            }
+
+            self.expression();
+            self.consume(TokenType::SemiColon, "Expect ';' after return value.");
+            self.emit_code(OpCode::Return);
+        }
+    }
+
     fn expression_statement(&mut self) {
+        let start = self.parser.current.span;
+        let side_effects = self.side_effects;
         self.expression();
+        if self.echo
+            && self.current.function_type == FunctionType::Script
+            && self.current.scope_depth == 0
+            && self.check(TokenType::Eof)
+        {
+            self.emit_code(OpCode::Print);
+            return;
+        }
+        if self.side_effects == side_effects {
+            let span = Span {
+                end: self.parser.previous.span.end,
+                ..start
+            };
+            self.warn_at(
+                WarningKind::NoEffect,
+                span,
+                "Expression statement has no effect.",
+            );
+        }
         self.consume(TokenType::SemiColon, "Expect ';' expression.");
         self.emit_code(OpCode::Pop);
     }
@@ -142,6 +673,22 @@ impl<'a> Compiler<'a> {
 
     fn advance(&mut self) {
         self.parser.previous = self.parser.current.clone();
+        if self.error_limit_reached() {
+            // Give up: pretend the source ends here, and say so once if
+            // there was anything left to compile.
+            let span = self.parser.previous.span;
+            if !self.parser.stopped_early && !self.scanner.scan_token().is(TokenType::Eof) {
+                self.parser.stopped_early = true;
+                let message = format!(
+                    "Too many errors; stopping after {}.",
+                    self.parser.diagnostics.len()
+                );
+                let diagnostic = self.diagnostic(span, &message, None);
+                self.parser.diagnostics.push(diagnostic);
+            }
+            self.parser.current = Token::new(TokenType::Eof, String::new(), span);
+            return;
+        }
 
         loop {
             self.parser.current = self.scanner.scan_token();
@@ -149,7 +696,13 @@ impl<'a> Compiler<'a> {
                 break;
             }
 
-            self.error_at_current(&self.parser.current.as_string())
+            // Bad characters and strings are independent of each other and
+            // of the statement around them, so report them all.
+            self.parser.panic_mode.replace(true);
+            self.report(
+                self.parser.current.clone(),
+                &self.parser.current.as_string(),
+            );
         }
     }
 
@@ -162,11 +715,19 @@ impl<'a> Compiler<'a> {
     }
 
     fn emit_byte(&mut self, bytes: u8) {
-        self.chunk.write(bytes, self.parser.previous.line);
+        let span = self.parser.previous.span;
+        self.current_chunk().write(bytes, span);
     }
 
     fn emit_code(&mut self, code: OpCode) {
-        self.chunk.write_opcode(code, self.parser.previous.line);
+        let span = self.parser.previous.span;
+        self.current_chunk().write_opcode(code, span);
+    }
+
+    /// Emits an instruction attributed to an earlier token, such as the
+    /// operator of a binary expression once its right operand is compiled.
+    fn emit_code_at(&mut self, code: OpCode, span: Span) {
+        self.current_chunk().write_opcode(code, span);
     }
 
     fn emit_bytes(&mut self, code: OpCode, operand: u8) {
@@ -174,50 +735,197 @@ impl<'a> Compiler<'a> {
         self.emit_byte(operand);
     }
 
-    fn emit_return(&mut self) {
-        self.emit_byte(OpCode::Return.into());
+    fn emit_loop(&mut self, loop_start: usize) {
+        self.emit_code(OpCode::Loop);
+
+        let offset = self.current_chunk().count() - loop_start + 2;
+        if offset > u16::MAX as usize {
+            self.error_at_previous("Loop body too large.");
+        }
+
+        self.emit_byte(((offset >> 8) & 0xff) as u8);
+        self.emit_byte((offset & 0xff) as u8);
     }
 
-    fn make_constant(&mut self, value: Value) -> u8 {
-        if let Some(constant) = self.chunk.add_constant(value) {
-            constant
+    fn emit_jump(&mut self, instruction: OpCode) -> usize {
+        self.emit_code(instruction);
+        self.emit_byte(0xff);
+        self.emit_byte(0xff);
+        self.current_chunk().count() - 2
+    }
+
+    fn patch_jump(&mut self, offset: usize) {
+        // -2 to adjust for the bytecode for the jump offset itself.
+        let jump = self.current_chunk().count() - offset - 2;
+
+        if jump > u16::MAX as usize {
+            self.error_at_previous("Too much code to jump over.");
+        }
+
+        self.current_chunk()
+            .patch(offset, ((jump >> 8) & 0xff) as u8);
+        self.current_chunk().patch(offset + 1, (jump & 0xff) as u8);
+    }
+
+    fn emit_return(&mut self) {
+        if self.current.function_type == FunctionType::Initializer {
+            self.emit_bytes(OpCode::GetLocal, 0);
         } else {
+            self.emit_code(OpCode::Nil);
+        }
+        self.emit_code(OpCode::Return);
+    }
+
+    fn make_constant(&mut self, value: Value) -> usize {
+        let constant = self.current_chunk().add_constant(value);
+        if constant > MAX_CONSTANT_INDEX {
             self.error_at_previous("Too many constants in one chunk.");
-            0
+            return 0;
         }
+        constant
     }
 
     fn emit_constant(&mut self, value: Value) {
         let constant = self.make_constant(value);
-        self.emit_bytes(OpCode::Constant, constant);
+        self.emit_indexed(OpCode::Constant, constant);
     }
 
-    fn end_compiler(&mut self) {
+    /// Emits `code` with a one byte operand, switching to its long form with a
+    /// 24-bit operand when `index` doesn't fit.
+    fn emit_indexed(&mut self, code: OpCode, index: usize) {
+        let span = self.parser.previous.span;
+        self.emit_indexed_at(code, index, span);
+    }
+
+    /// Like `emit_indexed`, attributing the instruction to an earlier token,
+    /// such as the target of an assignment.
+    fn emit_indexed_at(&mut self, code: OpCode, index: usize, span: Span) {
+        let chunk = self.current_chunk();
+        if let Ok(index) = u8::try_from(index) {
+            chunk.write_opcode(code, span);
+            chunk.write(index, span);
+            return;
+        }
+
+        match code.long_form() {
+            Some(long) => {
+                chunk.write_opcode(long, span);
+                chunk.write(((index >> 16) & 0xff) as u8, span);
+                chunk.write(((index >> 8) & 0xff) as u8, span);
+                chunk.write((index & 0xff) as u8, span);
+            }
+            None => self.error_at_previous("Too many constants in one chunk."),
+        }
+    }
+
+    fn end_compiler(&mut self) -> Box<FunctionCompiler> {
+        for local in std::mem::take(&mut self.current.locals) {
+            self.warn_if_unused(local);
+        }
         self.emit_return();
-        #[cfg(feature = "debug_print_code")]
-        if !*self.parser.had_error.borrow() {
-            self.chunk.disassemble("disassemble code")
+        if let Some(trace) = self.trace.as_deref_mut() {
+            if self.parser.diagnostics.is_empty() {
+                let function = &self.current.function;
+                let _ = function.chunk.disassemble(trace, function);
+            }
         }
+
+        let enclosing = self.current.enclosing.take();
+        let mut compiler = match enclosing {
+            Some(enclosing) => std::mem::replace(&mut self.current, enclosing),
+            None => std::mem::replace(
+                &mut self.current,
+                Box::new(FunctionCompiler::new(None, FunctionType::Script)),
+            ),
+        };
+        compiler.function.upvalue_count = compiler.upvalues.len();
+        compiler
     }
 
     fn binary(&mut self) {
         let operator_type = self.parser.previous.ttype;
+        let operator_span = self.parser.previous.span;
         let rule = self.get_rule(operator_type);
 
         self.parse_precedence(rule.precedence.next());
 
-        match operator_type {
-            TokenType::BangEqual => self.emit_code(OpCode::BangEqual),
-            TokenType::Equal => self.emit_byte(OpCode::Equal.into()),
-            TokenType::Greater => self.emit_byte(OpCode::Greater.into()),
-            TokenType::GreaterEqual => self.emit_code(OpCode::GreaterEqual),
-            TokenType::Less => self.emit_byte(OpCode::Less.into()),
-            TokenType::LessEqual => self.emit_code(OpCode::LessEqual),
-            TokenType::Plus => self.emit_byte(OpCode::Add.into()),
-            TokenType::Minus => self.emit_byte(OpCode::Subtract.into()),
-            TokenType::Star => self.emit_byte(OpCode::Multiply.into()),
-            TokenType::Slash => self.emit_byte(OpCode::Divide.into()),
-            _ => {}
+        let code = match operator_type {
+            TokenType::BangEqual => OpCode::BangEqual,
+            TokenType::Equal => OpCode::Equal,
+            TokenType::Greater => OpCode::Greater,
+            TokenType::GreaterEqual => OpCode::GreaterEqual,
+            TokenType::Less => OpCode::Less,
+            TokenType::LessEqual => OpCode::LessEqual,
+            TokenType::Plus => OpCode::Add,
+            TokenType::Minus => OpCode::Subtract,
+            TokenType::Star => OpCode::Multiply,
+            TokenType::Slash => OpCode::Divide,
+            _ => return,
+        };
+        self.emit_code_at(code, operator_span);
+    }
+
+    fn and(&mut self) {
+        let end_jump = self.emit_jump(OpCode::JumpIfFalse);
+
+        self.emit_code(OpCode::Pop);
+        self.parse_precedence(Precedence::And);
+
+        self.patch_jump(end_jump);
+    }
+
+    fn or(&mut self) {
+        let else_jump = self.emit_jump(OpCode::JumpIfFalse);
+        let end_jump = self.emit_jump(OpCode::Jump);
+
+        self.patch_jump(else_jump);
+        self.emit_code(OpCode::Pop);
+
+        self.parse_precedence(Precedence::Or);
+        self.patch_jump(end_jump);
+    }
+
+    fn call(&mut self) {
+        self.side_effects += 1;
+        let arg_count = self.argument_list();
+        self.emit_bytes(OpCode::Call, arg_count);
+    }
+
+    fn argument_list(&mut self) -> u8 {
+        let mut arg_count: usize = 0;
+        if !self.check(TokenType::RightParen) {
+            loop {
+                self.expression();
+                if arg_count == 255 {
+                    self.error_at_previous("Can't have more than 255 arguments.");
+                }
+                arg_count += 1;
+
+                if !self.is_match(TokenType::Comma) {
+                    break;
+                }
+            }
+        }
+        self.close_paren("Expect ')' after arguments.");
+        arg_count as u8
+    }
+
+    fn dot(&mut self, can_assign: bool) {
+        self.consume(TokenType::Identifier, "Expect property name after '.'.");
+        let span = self.parser.previous.span;
+        let name = self.identifier_constant(self.parser.previous.as_string());
+
+        if can_assign && self.is_match(TokenType::Assign) {
+            self.side_effects += 1;
+            self.expression();
+            self.emit_indexed_at(OpCode::SetProperty, name, span);
+        } else if self.is_match(TokenType::LeftParen) {
+            self.side_effects += 1;
+            let arg_count = self.argument_list();
+            self.emit_indexed(OpCode::Invoke, name);
+            self.emit_byte(arg_count);
+        } else {
+            self.emit_indexed(OpCode::GetProperty, name);
         }
     }
 
@@ -232,7 +940,7 @@ impl<'a> Compiler<'a> {
 
     fn grouping(&mut self) {
         self.expression();
-        self.consume(TokenType::RightParen, "Expect ')' after expression.");
+        self.close_paren("Expect ')' after expression.");
     }
 
     fn number(&mut self) {
@@ -243,30 +951,89 @@ impl<'a> Compiler<'a> {
     fn string(&mut self) {
         let len = self.parser.previous.lexeme.len() - 1;
         let value = self.parser.previous.as_string()[1..len].to_string();
+        let value = self.heap.intern(value);
         self.emit_constant(Value::Obj(Object::Str(value)))
     }
 
-    fn variable(&mut self) {
-        self.name_variable(self.parser.previous.as_string());
+    fn variable(&mut self, can_assign: bool) {
+        self.name_variable(self.parser.previous.as_string(), can_assign);
     }
 
-    fn name_variable(&mut self, name: String) {
-        let arg = self.identifier_constant(name);
-        self.emit_bytes(OpCode::GetGlobal, arg);
+    fn name_variable(&mut self, name: String, can_assign: bool) {
+        let span = self.parser.previous.span;
+        let mut suggestion = None;
+        let (get_op, set_op, arg) = if let Some(arg) = self.resolve_local(&name) {
+            (OpCode::GetLocal, OpCode::SetLocal, arg as usize)
+        } else if let Some(arg) = self.resolve_upvalue(&name) {
+            (OpCode::GetUpvalue, OpCode::SetUpvalue, arg as usize)
+        } else {
+            // Undefined globals are only caught at runtime, when the locals in
+            // scope here are long gone, so remember the likeliest one now.
+            suggestion = closest(&name, self.current.local_names()).map(str::to_string);
+            let arg = self.identifier_constant(name);
+            (OpCode::GetGlobal, OpCode::SetGlobal, arg)
+        };
+
+        let code = if can_assign && self.is_match(TokenType::Assign) {
+            self.side_effects += 1;
+            self.expression();
+            set_op
+        } else {
+            if get_op == OpCode::GetLocal {
+                self.current.locals[arg].is_used = true;
+            }
+            get_op
+        };
+        if let Some(suggestion) = suggestion {
+            let offset = self.current_chunk().count();
+            self.current_chunk().add_suggestion(offset, suggestion);
+        }
+        self.emit_indexed_at(code, arg, span);
+    }
+
+    fn super_(&mut self) {
+        match &self.current_class {
+            None => self.error_at_previous("Can't use 'super' outside of a class."),
+            Some(class) if !class.has_superclass => {
+                self.error_at_previous("Can't use 'super' in a class with no superclass.")
+            }
+            _ => {}
+        }
+
+        self.consume(TokenType::Dot, "Expect '.' after 'super'.");
+        self.consume(TokenType::Identifier, "Expect superclass method name.");
+        let name = self.identifier_constant(self.parser.previous.as_string());
+
+        self.name_variable("this".to_string(), false);
+        if self.is_match(TokenType::LeftParen) {
+            self.side_effects += 1;
+            let arg_count = self.argument_list();
+            self.name_variable("super".to_string(), false);
+            self.emit_indexed(OpCode::SuperInvoke, name);
+            self.emit_byte(arg_count);
+        } else {
+            self.name_variable("super".to_string(), false);
+            self.emit_indexed(OpCode::GetSuper, name);
+        }
+    }
+
+    fn this(&mut self) {
+        if self.current_class.is_none() {
+            self.error_at_previous("Can't use 'this' outside of a class.");
+            return;
+        }
+        self.variable(false);
     }
 
     fn unary(&mut self) {
         let operator_type = self.parser.previous.ttype;
+        let operator_span = self.parser.previous.span;
 
         self.parse_precedence(Precedence::Unary);
 
         match operator_type {
-            TokenType::Minus => {
-                self.emit_byte(OpCode::Negate.into());
-            }
-            TokenType::Bang => {
-                self.emit_byte(OpCode::Not.into());
-            }
+            TokenType::Minus => self.emit_code_at(OpCode::Negate, operator_span),
+            TokenType::Bang => self.emit_code_at(OpCode::Not, operator_span),
             _ => self.error_at_current("Unsupported Operand type."),
         }
     }
@@ -274,14 +1041,19 @@ impl<'a> Compiler<'a> {
     fn parse_precedence(&mut self, precedence: Precedence) {
         self.advance();
         if let Some(prefix_rule) = self.get_rule(self.parser.previous.ttype).prefix {
-            prefix_rule(self);
+            let can_assign = precedence <= Precedence::Assignment;
+            prefix_rule(self, can_assign);
 
             while precedence <= self.get_rule(self.parser.current.ttype).precedence {
                 self.advance();
                 if let Some(infix_rule) = self.get_rule(self.parser.previous.ttype).infix {
-                    infix_rule(self)
+                    infix_rule(self, can_assign)
                 }
             }
+
+            if can_assign && self.is_match(TokenType::Assign) {
+                self.error_at_previous("Invalid assignment target.");
+            }
         } else {
             self.error_at_previous("Expected expression.");
         }
@@ -290,43 +1062,43 @@ impl<'a> Compiler<'a> {
     fn get_rule(&self, ttype: TokenType) -> ParseRule {
         match ttype {
             TokenType::LeftParen => ParseRule {
-                prefix: Some(|c| c.grouping()),
-                infix: None,
-                precedence: Precedence::None,
+                prefix: Some(|c, _| c.grouping()),
+                infix: Some(|c, _| c.call()),
+                precedence: Precedence::Call,
             },
             TokenType::Minus => ParseRule {
-                prefix: Some(|c| c.unary()),
-                infix: Some(|c| c.binary()),
+                prefix: Some(|c, _| c.unary()),
+                infix: Some(|c, _| c.binary()),
                 precedence: Precedence::Term,
             },
             TokenType::Plus => ParseRule {
                 prefix: None,
-                infix: Some(|c| c.binary()),
+                infix: Some(|c, _| c.binary()),
                 precedence: Precedence::Term,
             },
             TokenType::Slash | TokenType::Star => ParseRule {
                 prefix: None,
-                infix: Some(|c| c.binary()),
+                infix: Some(|c, _| c.binary()),
                 precedence: Precedence::Factor,
             },
             TokenType::Number => ParseRule {
-                prefix: Some(|c| c.number()),
+                prefix: Some(|c, _| c.number()),
                 infix: None,
                 precedence: Precedence::None,
             },
             TokenType::False | TokenType::True | TokenType::Nil => ParseRule {
-                prefix: Some(|c| c.literal()),
+                prefix: Some(|c, _| c.literal()),
                 infix: None,
                 precedence: Precedence::None,
             },
             TokenType::Bang => ParseRule {
-                prefix: Some(|c| c.unary()),
+                prefix: Some(|c, _| c.unary()),
                 infix: None,
                 precedence: Precedence::None,
             },
             TokenType::BangEqual | TokenType::Equal => ParseRule {
                 prefix: None,
-                infix: Some(|c| c.binary()),
+                infix: Some(|c, _| c.binary()),
                 precedence: Precedence::Equality,
             },
             TokenType::Greater
@@ -334,19 +1106,44 @@ impl<'a> Compiler<'a> {
             | TokenType::Less
             | TokenType::LessEqual => ParseRule {
                 prefix: None,
-                infix: Some(|c| c.binary()),
+                infix: Some(|c, _| c.binary()),
                 precedence: Precedence::Comparison,
             },
             TokenType::String => ParseRule {
-                prefix: Some(|c| c.string()),
+                prefix: Some(|c, _| c.string()),
                 infix: None,
                 precedence: Precedence::None,
             },
             TokenType::Identifier => ParseRule {
-                prefix: Some(|c| c.variable()),
+                prefix: Some(|c, can_assign| c.variable(can_assign)),
                 infix: None,
                 precedence: Precedence::None,
             },
+            TokenType::Dot => ParseRule {
+                prefix: None,
+                infix: Some(|c, can_assign| c.dot(can_assign)),
+                precedence: Precedence::Call,
+            },
+            TokenType::Super => ParseRule {
+                prefix: Some(|c, _| c.super_()),
+                infix: None,
+                precedence: Precedence::None,
+            },
+            TokenType::This => ParseRule {
+                prefix: Some(|c, _| c.this()),
+                infix: None,
+                precedence: Precedence::None,
+            },
+            TokenType::And => ParseRule {
+                prefix: None,
+                infix: Some(|c, _| c.and()),
+                precedence: Precedence::And,
+            },
+            TokenType::Or => ParseRule {
+                prefix: None,
+                infix: Some(|c, _| c.or()),
+                precedence: Precedence::Or,
+            },
             _ => ParseRule {
                 prefix: None,
                 infix: None,
@@ -372,18 +1169,53 @@ impl<'a> Compiler<'a> {
             return;
         }
         self.parser.panic_mode.replace(true);
-        eprint!("[line {}] Error", token.line);
+        self.report(token, message);
+    }
 
-        if token.is(TokenType::Eof) {
-            eprint!(" at end.");
-        } else if token.is(TokenType::Error) {
-        } else {
-            eprint!(" at '{}'", token.as_string());
+    fn error_limit_reached(&self) -> bool {
+        let errors = self.parser.diagnostics.len();
+        errors > 0 && self.max_errors.is_some_and(|max| errors >= max)
+    }
+
+    fn report(&mut self, token: Token, message: &str) {
+        if self.error_limit_reached() {
+            return;
         }
 
-        eprintln!(": {message}");
+        let lexeme = match token.ttype {
+            TokenType::Eof => Some(String::new()),
+            TokenType::Error => None,
+            _ => Some(token.as_string()),
+        };
+        let mut diagnostic = self.diagnostic(token.span, message, lexeme);
+        diagnostic.incomplete = match token.ttype {
+            TokenType::Eof => true,
+            TokenType::Error => self.scanner.is_unterminated(),
+            _ => false,
+        };
+        self.parser.diagnostics.push(diagnostic);
+    }
+
+    fn warn_at(&mut self, kind: WarningKind, span: Span, message: &str) {
+        if !self.enabled_warnings.contains(&kind) {
+            return;
+        }
+        let diagnostic = self.diagnostic(span, message, None);
+        self.parser.warnings.push(Warning { kind, diagnostic });
+    }
 
-        self.parser.had_error.replace(true);
+    fn diagnostic(&self, span: Span, message: &str, lexeme: Option<String>) -> Diagnostic {
+        Diagnostic {
+            message: message.to_string(),
+            line: span.line,
+            column: span.column,
+            span: span.start..span.end,
+            source_line: source_line(&self.source, span.start),
+            lexeme,
+            help: None,
+            stack_trace: Vec::new(),
+            incomplete: false,
+        }
     }
 }
 
@@ -391,11 +1223,146 @@ impl<'a> Compiler<'a> {
 pub struct Parser {
     current: Token,
     previous: Token,
-    had_error: RefCell<bool>,
+    diagnostics: Vec<Diagnostic>,
+    warnings: Vec<Warning>,
     panic_mode: RefCell<bool>,
+    /// Blocks and class bodies the parser is inside.
+    open_braces: usize,
+    /// Whether the error limit was reached with source left to compile.
+    stopped_early: bool,
+}
+
+pub const DEFAULT_MAX_ERRORS: usize = 20;
+const UINT8_COUNT: usize = u8::MAX as usize + 1;
+const MAX_CONSTANT_INDEX: usize = 0xff_ffff;
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+enum FunctionType {
+    Function,
+    Initializer,
+    Method,
+    Script,
+}
+
+struct FunctionCompiler {
+    enclosing: Option<Box<FunctionCompiler>>,
+    function: Function,
+    function_type: FunctionType,
+    locals: Vec<Local>,
+    upvalues: Vec<Upvalue>,
+    scope_depth: usize,
+}
+
+impl FunctionCompiler {
+    fn new(name: Option<String>, function_type: FunctionType) -> Self {
+        // The first slot is claimed by the function being called, or by the
+        // receiver inside methods.
+        let slot_name = if function_type == FunctionType::Function {
+            Token::default()
+        } else {
+            Token::new(TokenType::This, "this".to_string(), Span::default())
+        };
+        let locals = vec![Local {
+            name: slot_name,
+            depth: Some(0),
+            is_captured: false,
+            is_used: true,
+        }];
+        Self {
+            enclosing: None,
+            function: Function::new(name),
+            function_type,
+            locals,
+            upvalues: Vec::new(),
+            scope_depth: 0,
+        }
+    }
+
+    /// Names of the initialized locals visible from this function, innermost
+    /// first.
+    fn local_names(&self) -> Vec<&str> {
+        let mut names = Vec::new();
+        let mut compiler = Some(self);
+        while let Some(current) = compiler {
+            let locals = current.locals.iter().rev();
+            names.extend(
+                locals
+                    .filter(|local| local.depth.is_some() && !local.name.lexeme.is_empty())
+                    .map(|local| local.name.lexeme.as_str()),
+            );
+            compiler = current.enclosing.as_deref();
+        }
+        names
+    }
+
+    fn resolve_local(&self, name: &str) -> Result<Option<u8>, &'static str> {
+        for (i, local) in self.locals.iter().enumerate().rev() {
+            if local.name.lexeme == name {
+                if local.depth.is_none() {
+                    return Err("Can't read local variable in its own initializer.");
+                }
+                return Ok(Some(i as u8));
+            }
+        }
+        Ok(None)
+    }
+
+    fn resolve_upvalue(&mut self, name: &str) -> Result<Option<u8>, &'static str> {
+        let enclosing = match self.enclosing.as_mut() {
+            Some(enclosing) => enclosing,
+            None => return Ok(None),
+        };
+
+        if let Some(local) = enclosing.resolve_local(name)? {
+            // Count capturing as reading, whatever the closure does with it.
+            enclosing.locals[local as usize].is_captured = true;
+            enclosing.locals[local as usize].is_used = true;
+            return self.add_upvalue(local, true).map(Some);
+        }
+
+        if let Some(upvalue) = enclosing.resolve_upvalue(name)? {
+            return self.add_upvalue(upvalue, false).map(Some);
+        }
+
+        Ok(None)
+    }
+
+    fn add_upvalue(&mut self, index: u8, is_local: bool) -> Result<u8, &'static str> {
+        if let Some(i) = self
+            .upvalues
+            .iter()
+            .position(|upvalue| upvalue.index == index && upvalue.is_local == is_local)
+        {
+            return Ok(i as u8);
+        }
+
+        if self.upvalues.len() == UINT8_COUNT {
+            return Err("Too many closure variables in function.");
+        }
+
+        self.upvalues.push(Upvalue { index, is_local });
+        Ok((self.upvalues.len() - 1) as u8)
+    }
+}
+
+struct ClassCompiler {
+    enclosing: Option<Box<ClassCompiler>>,
+    has_superclass: bool,
+}
+
+struct Local {
+    name: Token,
+    depth: Option<usize>,
+    is_captured: bool,
+    is_used: bool,
+}
+
+struct Upvalue {
+    index: u8,
+    is_local: bool,
 }
 
-type ParseFn = fn(&mut Compiler);
+type ParseFn = fn(&mut Compiler, bool);
 
 struct ParseRule {
     prefix: Option<ParseFn>,
@@ -0,0 +1,206 @@
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::io::Write;
+use std::ops::Range;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ErrorKind {
+    Compile,
+    Runtime,
+}
+
+/// Everything that went wrong in one call to `Vm::interpret`.
+#[derive(Debug, Clone)]
+pub struct LoxError {
+    pub kind: ErrorKind,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl LoxError {
+    pub fn new(kind: ErrorKind, diagnostics: Vec<Diagnostic>) -> Self {
+        Self { kind, diagnostics }
+    }
+
+    /// Writes every diagnostic with the source it points at, plus the stack
+    /// trace of a runtime error.
+    pub fn write_report(&self, out: &mut dyn Write) -> io::Result<()> {
+        for diagnostic in &self.diagnostics {
+            match self.kind {
+                ErrorKind::Compile => {
+                    let location = match diagnostic.lexeme.as_deref() {
+                        None => String::new(),
+                        Some("") => " at end".to_string(),
+                        Some(lexeme) => format!(" at '{lexeme}'"),
+                    };
+                    writeln!(
+                        out,
+                        "[line {}] Error{location}: {}",
+                        diagnostic.line, diagnostic.message
+                    )?;
+                    diagnostic.write_snippet(out)?;
+                }
+                ErrorKind::Runtime => {
+                    writeln!(out, "{}", diagnostic.message)?;
+                    diagnostic.write_snippet(out)?;
+                    for frame in &diagnostic.stack_trace {
+                        match &frame.function {
+                            Some(name) => writeln!(out, "[line {}] in {name}().", frame.line)?,
+                            None => writeln!(out, "[line {}] in script.", frame.line)?,
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Display for LoxError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for (i, diagnostic) in self.diagnostics.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "[line {}] {}", diagnostic.line, diagnostic.message)?;
+            if let Some(help) = &diagnostic.help {
+                write!(f, " ({help})")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for LoxError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub message: String,
+    pub line: usize,
+    /// Column of the first character the error points at, counting from 1.
+    pub column: usize,
+    /// Byte range of the source the error points at.
+    pub span: Range<usize>,
+    /// The full source line containing the start of `span`.
+    pub source_line: String,
+    /// Source text of the token the error was reported at, empty at the end
+    /// of the input. `None` for scanner and runtime errors.
+    pub lexeme: Option<String>,
+    /// A hint at a likely fix, such as the name a typo was probably meant to be.
+    pub help: Option<String>,
+    /// Call frames active when a runtime error occurred, innermost first.
+    pub stack_trace: Vec<StackFrame>,
+    /// Whether the source ended too soon, inside an unclosed block, call or
+    /// string, so more input could fix the error.
+    pub incomplete: bool,
+}
+
+impl Diagnostic {
+    /// Quotes the source line the diagnostic points at and underlines its span.
+    fn write_snippet(&self, out: &mut dyn Write) -> io::Result<()> {
+        let source_line = &self.source_line;
+        let skipped = self.column.saturating_sub(1);
+        // Keep tabs in the indent so the carets line up with the quoted text.
+        let indent: String = source_line
+            .chars()
+            .take(skipped)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let mut bytes = 0;
+        let width = source_line
+            .chars()
+            .skip(skipped)
+            .take_while(|c| {
+                bytes += c.len_utf8();
+                bytes <= self.span.len()
+            })
+            .count()
+            .max(1);
+
+        let gutter = self.line.to_string().len();
+        writeln!(out, "{} | {source_line}", self.line)?;
+        writeln!(out, "{:gutter$} | {indent}{}", "", "^".repeat(width))?;
+        if let Some(help) = &self.help {
+            writeln!(out, "{:gutter$} = help: {help}", "")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StackFrame {
+    /// Name of the function, `None` for top-level script code.
+    pub function: Option<String>,
+    pub line: usize,
+    pub column: usize,
+}
+
+/// Something legal but probably unintended, found while compiling.
+#[derive(Debug, Clone)]
+pub struct Warning {
+    pub kind: WarningKind,
+    pub diagnostic: Diagnostic,
+}
+
+impl Warning {
+    pub fn write_report(&self, out: &mut dyn Write) -> io::Result<()> {
+        let diagnostic = &self.diagnostic;
+        writeln!(
+            out,
+            "[line {}] Warning ({}): {}",
+            diagnostic.line,
+            self.kind.name(),
+            diagnostic.message
+        )?;
+        diagnostic.write_snippet(out)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum WarningKind {
+    /// A local variable that is declared but never read.
+    UnusedVariable,
+    /// Statements following a `return` in the same block.
+    UnreachableCode,
+    /// An expression statement that neither calls nor assigns anything.
+    NoEffect,
+    /// A local variable with the same name as one in an enclosing scope.
+    Shadowing,
+}
+
+impl WarningKind {
+    pub const ALL: [WarningKind; 4] = [
+        WarningKind::UnusedVariable,
+        WarningKind::UnreachableCode,
+        WarningKind::NoEffect,
+        WarningKind::Shadowing,
+    ];
+
+    /// The name used to turn the warning on or off from the command line.
+    pub fn name(self) -> &'static str {
+        match self {
+            WarningKind::UnusedVariable => "unused-variable",
+            WarningKind::UnreachableCode => "unreachable-code",
+            WarningKind::NoEffect => "no-effect",
+            WarningKind::Shadowing => "shadowing",
+        }
+    }
+}
+
+/// Returns the line of `source` containing byte `offset`, without its line
+/// terminator.
+pub(crate) fn source_line(source: &str, offset: usize) -> String {
+    let offset = offset.min(source.len());
+    let start = line_start(source, offset);
+    let end = source[offset..]
+        .find('\n')
+        .map_or(source.len(), |i| offset + i);
+    source[start..end].trim_end_matches('\r').to_string()
+}
+
+/// Byte offset of the start of the line containing `offset`.
+pub(crate) fn line_start(source: &str, offset: usize) -> usize {
+    source
+        .get(..offset)
+        .and_then(|before| before.rfind('\n'))
+        .map_or(0, |i| i + 1)
+}
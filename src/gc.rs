@@ -0,0 +1,214 @@
+use crate::object::{LoxString, Object};
+use crate::value::Value;
+use std::borrow::Borrow;
+use std::cell::Cell;
+use std::collections::HashSet;
+use std::fmt::{Debug, Display, Formatter};
+use std::hash::{Hash, Hasher};
+use std::ops::Deref;
+use std::ptr::NonNull;
+
+const GC_HEAP_GROW_FACTOR: usize = 2;
+const GC_INITIAL_THRESHOLD: usize = 1024 * 1024;
+
+pub trait Trace {
+    /// Marks every object directly reachable from this one.
+    fn trace(&self, heap: &mut Heap);
+
+    /// Bytes owned by this object outside of its own allocation.
+    fn size(&self) -> usize {
+        0
+    }
+}
+
+struct GcBox<T: ?Sized> {
+    marked: Cell<bool>,
+    size: usize,
+    value: T,
+}
+
+/// A handle to an object owned by a `Heap`. Handles are only valid while the
+/// object is reachable from the VM roots, the heap frees everything else on
+/// the next collection.
+pub struct Gc<T: 'static> {
+    ptr: NonNull<GcBox<T>>,
+}
+
+impl<T> Gc<T> {
+    pub fn ptr_eq(a: &Gc<T>, b: &Gc<T>) -> bool {
+        a.ptr == b.ptr
+    }
+}
+
+impl<T> Gc<T> {
+    fn is_marked(&self) -> bool {
+        unsafe { self.ptr.as_ref().marked.get() }
+    }
+}
+
+impl<T> Copy for Gc<T> {}
+
+impl<T> Clone for Gc<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Deref for Gc<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &self.ptr.as_ref().value }
+    }
+}
+
+impl<T> Debug for Gc<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Gc({:p})", self.ptr)
+    }
+}
+
+impl<T: Display> Display for Gc<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+/// Entry of the interning table, looked up by the string contents.
+struct InternedStr(Gc<LoxString>);
+
+impl Hash for InternedStr {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.as_str().hash(state);
+    }
+}
+
+impl PartialEq for InternedStr {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.as_str() == other.0.as_str()
+    }
+}
+
+impl Eq for InternedStr {}
+
+impl Borrow<str> for InternedStr {
+    fn borrow(&self) -> &str {
+        self.0.as_str()
+    }
+}
+
+pub struct Heap {
+    objects: Vec<NonNull<GcBox<dyn Trace>>>,
+    strings: HashSet<InternedStr>,
+    gray_stack: Vec<NonNull<GcBox<dyn Trace>>>,
+    bytes_allocated: usize,
+    next_gc: usize,
+}
+
+impl Heap {
+    pub fn new() -> Self {
+        Self {
+            objects: Vec::new(),
+            strings: HashSet::new(),
+            gray_stack: Vec::new(),
+            bytes_allocated: 0,
+            next_gc: GC_INITIAL_THRESHOLD,
+        }
+    }
+
+    pub fn alloc<T: Trace + 'static>(&mut self, value: T) -> Gc<T> {
+        let size = std::mem::size_of::<GcBox<T>>() + value.size();
+        let boxed = Box::new(GcBox {
+            marked: Cell::new(false),
+            size,
+            value,
+        });
+        let ptr = NonNull::from(Box::leak(boxed));
+        self.objects.push(ptr);
+        self.bytes_allocated += size;
+        Gc { ptr }
+    }
+
+    /// Returns the unique string object holding `chars`, allocating it the
+    /// first time it is seen.
+    pub fn intern(&mut self, chars: String) -> Gc<LoxString> {
+        if let Some(interned) = self.find_interned(&chars) {
+            return interned;
+        }
+        let string = self.alloc(LoxString::new(chars));
+        self.strings.insert(InternedStr(string));
+        string
+    }
+
+    pub fn find_interned(&self, chars: &str) -> Option<Gc<LoxString>> {
+        self.strings.get(chars).map(|interned| interned.0)
+    }
+
+    pub fn should_collect(&self) -> bool {
+        cfg!(feature = "debug_stress_gc") || self.bytes_allocated > self.next_gc
+    }
+
+    pub fn mark<T: Trace + 'static>(&mut self, gc: Gc<T>) {
+        let object = unsafe { gc.ptr.as_ref() };
+        if object.marked.get() {
+            return;
+        }
+        object.marked.set(true);
+        self.gray_stack.push(gc.ptr);
+    }
+
+    pub fn mark_value(&mut self, value: &Value) {
+        if let Value::Obj(object) = value {
+            self.mark_object(object);
+        }
+    }
+
+    pub fn mark_object(&mut self, object: &Object) {
+        match *object {
+            Object::Str(s) => self.mark(s),
+            Object::Function(f) => self.mark(f),
+            Object::Closure(c) => self.mark(c),
+            Object::Class(c) => self.mark(c),
+            Object::Instance(i) => self.mark(i),
+            Object::BoundMethod(b) => self.mark(b),
+            Object::Native(n) => self.mark(n),
+        }
+    }
+
+    /// Blackens gray objects until every reachable object has been visited.
+    pub fn trace_references(&mut self) {
+        while let Some(ptr) = self.gray_stack.pop() {
+            let object = unsafe { ptr.as_ref() };
+            object.value.trace(self);
+        }
+    }
+
+    /// Frees every object left white by the mark phase.
+    pub fn sweep(&mut self) {
+        // The interning table holds its strings weakly.
+        self.strings.retain(|string| string.0.is_marked());
+
+        let mut freed = 0;
+        self.objects.retain(|ptr| {
+            let object = unsafe { ptr.as_ref() };
+            if object.marked.get() {
+                object.marked.set(false);
+                true
+            } else {
+                freed += object.size;
+                drop(unsafe { Box::from_raw(ptr.as_ptr()) });
+                false
+            }
+        });
+        self.bytes_allocated -= freed;
+        self.next_gc = (self.bytes_allocated * GC_HEAP_GROW_FACTOR).max(GC_INITIAL_THRESHOLD);
+    }
+}
+
+impl Drop for Heap {
+    fn drop(&mut self) {
+        for ptr in self.objects.drain(..) {
+            drop(unsafe { Box::from_raw(ptr.as_ptr()) });
+        }
+    }
+}
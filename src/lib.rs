@@ -0,0 +1,21 @@
+//! A bytecode virtual machine for the Lox language, usable as a library by
+//! embedding a [`Vm`] and exchanging [`LoxValue`]s with it through globals and
+//! native functions.
+
+mod chunk;
+mod compiler;
+mod error;
+mod gc;
+mod object;
+mod opcode;
+mod precedence;
+mod scanner;
+mod suggest;
+mod token;
+mod token_type;
+mod value;
+mod vm;
+
+pub use crate::error::{Diagnostic, ErrorKind, LoxError, StackFrame, Warning, WarningKind};
+pub use crate::value::LoxValue;
+pub use crate::vm::{Vm, WarningHandler};
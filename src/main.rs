@@ -1,63 +1,151 @@
-mod chunk;
-mod compiler;
-mod object;
-mod opcode;
-mod precedence;
-mod scanner;
-mod token;
-mod token_type;
-mod value;
-mod vm;
-
-use crate::vm::*;
+mod repl;
+
+use crate::repl::repl;
+use bytecode_lox::{ErrorKind, Vm, WarningKind};
 use std::env::args;
 use std::io;
-use std::io::{stdout, Write};
 
-fn main() {
-    let args: Vec<String> = args().collect();
-    let mut chunk = chunk::Chunk::new();
-    let mut vm = VM::new(&mut chunk);
-    match args.len() {
-        1 => {
-            repl(&mut vm);
-        }
-        2 => {
-            run_file(&mut vm, &args[1]).expect("Error: something is wrong");
-        }
-        _ => {
-            println!("Usage: bytecode-lox [script]");
-            std::process::exit(64);
+const USAGE: &str = "Usage: bytecode-lox [options] [script | -e <code> | -] [args...]";
+
+const OPTIONS: &str = "\
+Runs a Lox script, or starts a REPL when none is given.
+
+Options:
+  -e <code>       Run <code> instead of a script file
+  -               Read the script from standard input
+  --check         Compile the script and report errors without running it
+  --trace         Print the stack and every instruction as it executes
+  --disassemble   Print the bytecode of every compiled function
+  --max-errors <n>
+                  Stop compiling after <n> errors, 0 for no limit (default 20)
+  --warn <name>   Turn on a compiler warning
+  --no-warn <name>
+                  Turn off a compiler warning
+  --version       Print version information
+  -h, --help      Print this help
+
+Arguments after the script are available to it through the `args` global:
+`args.count` is their number and `args.get(i)` returns the i-th one.
+
+Warnings, all on by default: unused-variable, unreachable-code, no-effect and
+shadowing. 'all' names every one of them.
+";
+
+enum Source {
+    File(String),
+    Stdin,
+    Inline(String),
+}
+
+impl Source {
+    fn read(self) -> io::Result<String> {
+        match self {
+            Source::File(path) => std::fs::read_to_string(path),
+            Source::Stdin => io::read_to_string(io::stdin()),
+            Source::Inline(code) => Ok(code),
         }
     }
 }
 
-fn repl(vm: &mut VM) {
-    let stdin = io::stdin();
-    print!("> ");
-    let _ = stdout().flush();
-    for line in stdin.lines() {
-        if let Ok(line) = line {
-            if line.is_empty() {
-                println!("Please enter something to execute");
-                print!("> ");
-                let _ = stdout().flush();
-                continue;
+fn main() {
+    let mut vm = Vm::new();
+    vm.set_diagnostics(Box::new(io::stderr()));
+    let mut check = false;
+    let mut source = None;
+    let mut args = args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--trace" => vm.set_trace_execution(true),
+            "--disassemble" => vm.set_print_code(true),
+            "--check" => check = true,
+            "--max-errors" => {
+                let max_errors = args.next().and_then(|n| n.parse().ok());
+                match max_errors.unwrap_or_else(|| usage()) {
+                    0 => vm.set_max_errors(None),
+                    n => vm.set_max_errors(Some(n)),
+                }
+            }
+            "--warn" | "--no-warn" => {
+                let name = args.next().unwrap_or_else(|| usage());
+                for kind in warning_kinds(&name) {
+                    vm.set_warning(kind, arg == "--warn");
+                }
+            }
+            "--version" => {
+                println!("bytecode-lox {}", env!("CARGO_PKG_VERSION"));
+                return;
+            }
+            "-h" | "--help" => {
+                println!("{USAGE}\n");
+                print!("{OPTIONS}");
+                return;
             }
+            "-e" => {
+                source = Some(Source::Inline(args.next().unwrap_or_else(|| usage())));
+                break;
+            }
+            "-" => {
+                source = Some(Source::Stdin);
+                break;
+            }
+            _ if arg.starts_with('-') => usage(),
+            _ => {
+                source = Some(Source::File(arg));
+                break;
+            }
+        }
+    }
+    let script_args: Vec<String> = args.collect();
+    vm.set_args(&script_args);
+
+    match source {
+        Some(source) => run(&mut vm, source, check),
+        None if check => usage(),
+        None => repl(&mut vm),
+    }
+}
 
-            let _ = vm.interpret(&line);
-        } else {
-            break;
+fn usage() -> ! {
+    eprintln!("{USAGE}");
+    eprintln!("Try 'bytecode-lox --help' for more information.");
+    std::process::exit(64);
+}
+
+/// The warnings a `--warn` or `--no-warn` argument refers to.
+fn warning_kinds(name: &str) -> Vec<WarningKind> {
+    if name == "all" {
+        return WarningKind::ALL.to_vec();
+    }
+    match WarningKind::ALL
+        .into_iter()
+        .find(|kind| kind.name() == name)
+    {
+        Some(kind) => vec![kind],
+        None => {
+            eprintln!("Unknown warning '{name}'.");
+            usage()
         }
-        print!("> ");
-        let _ = stdout().flush();
     }
 }
-fn run_file(vm: &mut VM, path: &str) -> io::Result<()> {
-    let buf = std::fs::read_to_string(path)?;
-    match vm.interpret(&buf) {
-        Err(InterpretResult::RuntimeError) => std::process::exit(66),
-        Err(InterpretResult::CompileError) => std::process::exit(65),
-        Ok(_) => std::process::exit(0),
+
+fn run(vm: &mut Vm, source: Source, check: bool) {
+    let code = match source.read() {
+        Ok(code) => code,
+        Err(error) => {
+            eprintln!("Could not read script: {error}");
+            std::process::exit(74);
+        }
+    };
+
+    let result = if check {
+        vm.check(&code)
+    } else {
+        vm.interpret(&code)
+    };
+    if let Err(error) = result {
+        match error.kind {
+            ErrorKind::Runtime => std::process::exit(66),
+            ErrorKind::Compile => std::process::exit(65),
+        }
     }
 }
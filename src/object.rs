@@ -1,17 +1,46 @@
+use crate::chunk::Chunk;
+use crate::gc::{Gc, Heap, Trace};
 use crate::object::Object::*;
-use std::fmt::{Display, Formatter};
+use crate::value::{LoxValue, Value};
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt::{Debug, Display, Formatter};
+use std::hash::{Hash, Hasher};
 
-#[derive(Debug, PartialEq, Eq, PartialOrd)]
+#[derive(Debug, Copy, Clone)]
 pub enum Object {
-    Str(String),
+    Str(Gc<LoxString>),
+    Function(Gc<Function>),
+    Closure(Gc<Closure>),
+    Class(Gc<RefCell<Class>>),
+    Instance(Gc<RefCell<Instance>>),
+    BoundMethod(Gc<BoundMethod>),
+    Native(Gc<Native>),
 }
 
 impl Object {}
 
-impl Clone for Object {
-    fn clone(&self) -> Self {
-        match self {
-            Str(s) => Object::Str(s.clone()),
+impl PartialEq for Object {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Str(a), Str(b)) => Gc::ptr_eq(a, b),
+            (Function(a), Function(b)) => Gc::ptr_eq(a, b),
+            (Closure(a), Closure(b)) => Gc::ptr_eq(a, b),
+            (Class(a), Class(b)) => Gc::ptr_eq(a, b),
+            (Instance(a), Instance(b)) => Gc::ptr_eq(a, b),
+            (BoundMethod(a), BoundMethod(b)) => Gc::ptr_eq(a, b),
+            (Native(a), Native(b)) => Gc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+impl PartialOrd for Object {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        match (self, other) {
+            (Str(a), Str(b)) => a.as_str().partial_cmp(b.as_str()),
+            _ => None,
         }
     }
 }
@@ -22,6 +51,255 @@ impl Display for Object {
             Object::Str(v) => {
                 write!(f, "{v}")
             }
+            Object::Function(v) => {
+                write!(f, "{v}")
+            }
+            Object::Closure(v) => {
+                write!(f, "{}", v.function)
+            }
+            Object::Class(v) => {
+                write!(f, "{}", v.borrow().name)
+            }
+            Object::Instance(v) => {
+                write!(f, "{} instance", v.borrow().class.borrow().name)
+            }
+            Object::BoundMethod(v) => {
+                write!(f, "{}", v.method.function)
+            }
+            Object::Native(_) => {
+                write!(f, "<native fn>")
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct LoxString {
+    pub chars: String,
+    pub hash: u32,
+}
+
+impl LoxString {
+    pub fn new(chars: String) -> Self {
+        let hash = hash_string(&chars);
+        Self { chars, hash }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.chars
+    }
+}
+
+impl Display for LoxString {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.chars)
+    }
+}
+
+// Interned strings are unique, so the handle itself identifies the string and
+// the hash computed at allocation time can stand in for hashing the chars.
+impl Hash for Gc<LoxString> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_u32(self.hash);
+    }
+}
+
+impl PartialEq for Gc<LoxString> {
+    fn eq(&self, other: &Self) -> bool {
+        Gc::ptr_eq(self, other)
+    }
+}
+
+impl Eq for Gc<LoxString> {}
+
+/// FNV-1a, as used by clox.
+pub fn hash_string(key: &str) -> u32 {
+    let mut hash: u32 = 2166136261;
+    for byte in key.bytes() {
+        hash ^= byte as u32;
+        hash = hash.wrapping_mul(16777619);
+    }
+    hash
+}
+
+#[derive(Debug)]
+pub struct Function {
+    pub arity: usize,
+    pub upvalue_count: usize,
+    pub chunk: Chunk,
+    pub name: Option<String>,
+}
+
+impl Function {
+    pub fn new(name: Option<String>) -> Self {
+        Self {
+            arity: 0,
+            upvalue_count: 0,
+            chunk: Chunk::new(),
+            name,
+        }
+    }
+}
+
+impl Display for Function {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.name {
+            Some(name) => write!(f, "<fn {name}>"),
+            None => write!(f, "<script>"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Closure {
+    pub function: Gc<Function>,
+    pub upvalues: Vec<Gc<RefCell<Upvalue>>>,
+}
+
+impl Closure {
+    pub fn new(function: Gc<Function>, upvalues: Vec<Gc<RefCell<Upvalue>>>) -> Self {
+        Self { function, upvalues }
+    }
+}
+
+/// A variable captured by a closure. It points at a stack slot while the
+/// variable is still live and owns the value once the slot is popped.
+#[derive(Debug)]
+pub enum Upvalue {
+    Open(usize),
+    Closed(Value),
+}
+
+#[derive(Debug)]
+pub struct Class {
+    pub name: Gc<LoxString>,
+    pub methods: HashMap<Gc<LoxString>, Gc<Closure>>,
+}
+
+impl Class {
+    pub fn new(name: Gc<LoxString>) -> Self {
+        Self {
+            name,
+            methods: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Instance {
+    pub class: Gc<RefCell<Class>>,
+    pub fields: HashMap<Gc<LoxString>, Value>,
+}
+
+impl Instance {
+    pub fn new(class: Gc<RefCell<Class>>) -> Self {
+        Self {
+            class,
+            fields: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct BoundMethod {
+    pub receiver: Value,
+    pub method: Gc<Closure>,
+}
+
+impl BoundMethod {
+    pub fn new(receiver: Value, method: Gc<Closure>) -> Self {
+        Self { receiver, method }
+    }
+}
+
+/// Natives only see copies of their arguments and hand back an owned result,
+/// so they can't hold on to objects the garbage collector may free.
+pub type NativeFn = Box<dyn Fn(&[LoxValue]) -> Result<LoxValue, String>>;
+
+pub struct Native {
+    pub name: String,
+    pub arity: usize,
+    pub function: NativeFn,
+}
+
+impl Native {
+    pub fn new(name: String, arity: usize, function: NativeFn) -> Self {
+        Self {
+            name,
+            arity,
+            function,
+        }
+    }
+}
+
+impl Debug for Native {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<native fn {}>", self.name)
+    }
+}
+
+impl Trace for LoxString {
+    fn trace(&self, _heap: &mut Heap) {}
+
+    fn size(&self) -> usize {
+        self.chars.capacity()
+    }
+}
+
+impl Trace for Function {
+    fn trace(&self, heap: &mut Heap) {
+        for constant in self.chunk.constants() {
+            heap.mark_value(constant);
+        }
+    }
+}
+
+impl Trace for Closure {
+    fn trace(&self, heap: &mut Heap) {
+        heap.mark(self.function);
+        for upvalue in &self.upvalues {
+            heap.mark(*upvalue);
+        }
+    }
+}
+
+impl Trace for RefCell<Upvalue> {
+    fn trace(&self, heap: &mut Heap) {
+        if let Upvalue::Closed(value) = &*self.borrow() {
+            heap.mark_value(value);
         }
     }
 }
+
+impl Trace for RefCell<Class> {
+    fn trace(&self, heap: &mut Heap) {
+        let class = self.borrow();
+        heap.mark(class.name);
+        for (name, method) in &class.methods {
+            heap.mark(*name);
+            heap.mark(*method);
+        }
+    }
+}
+
+impl Trace for RefCell<Instance> {
+    fn trace(&self, heap: &mut Heap) {
+        let instance = self.borrow();
+        heap.mark(instance.class);
+        for (name, value) in &instance.fields {
+            heap.mark(*name);
+            heap.mark_value(value);
+        }
+    }
+}
+
+impl Trace for BoundMethod {
+    fn trace(&self, heap: &mut Heap) {
+        heap.mark_value(&self.receiver);
+        heap.mark(self.method);
+    }
+}
+
+impl Trace for Native {
+    fn trace(&self, _heap: &mut Heap) {}
+}
@@ -1,3 +1,4 @@
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum OpCode {
     Constant = 0,
     Return,
@@ -20,6 +21,76 @@ pub enum OpCode {
     Pop,
     DefineGlobal,
     GetGlobal,
+    SetGlobal,
+    GetLocal,
+    SetLocal,
+    Jump,
+    JumpIfFalse,
+    Loop,
+    Call,
+    Closure,
+    GetUpvalue,
+    SetUpvalue,
+    CloseUpvalue,
+    Class,
+    GetProperty,
+    SetProperty,
+    Method,
+    Invoke,
+    Inherit,
+    GetSuper,
+    SuperInvoke,
+    ConstantLong,
+    DefineGlobalLong,
+    GetGlobalLong,
+    SetGlobalLong,
+    ClosureLong,
+    ClassLong,
+    GetPropertyLong,
+    SetPropertyLong,
+    MethodLong,
+    InvokeLong,
+    GetSuperLong,
+    SuperInvokeLong,
+}
+
+impl OpCode {
+    /// The variant taking a 24-bit constant index, for instructions that have one.
+    pub fn long_form(self) -> Option<OpCode> {
+        match self {
+            OpCode::Constant => Some(OpCode::ConstantLong),
+            OpCode::DefineGlobal => Some(OpCode::DefineGlobalLong),
+            OpCode::GetGlobal => Some(OpCode::GetGlobalLong),
+            OpCode::SetGlobal => Some(OpCode::SetGlobalLong),
+            OpCode::Closure => Some(OpCode::ClosureLong),
+            OpCode::Class => Some(OpCode::ClassLong),
+            OpCode::GetProperty => Some(OpCode::GetPropertyLong),
+            OpCode::SetProperty => Some(OpCode::SetPropertyLong),
+            OpCode::Method => Some(OpCode::MethodLong),
+            OpCode::Invoke => Some(OpCode::InvokeLong),
+            OpCode::GetSuper => Some(OpCode::GetSuperLong),
+            OpCode::SuperInvoke => Some(OpCode::SuperInvokeLong),
+            _ => None,
+        }
+    }
+
+    pub fn is_long(self) -> bool {
+        matches!(
+            self,
+            OpCode::ConstantLong
+                | OpCode::DefineGlobalLong
+                | OpCode::GetGlobalLong
+                | OpCode::SetGlobalLong
+                | OpCode::ClosureLong
+                | OpCode::ClassLong
+                | OpCode::GetPropertyLong
+                | OpCode::SetPropertyLong
+                | OpCode::MethodLong
+                | OpCode::InvokeLong
+                | OpCode::GetSuperLong
+                | OpCode::SuperInvokeLong
+        )
+    }
 }
 
 impl From<u8> for OpCode {
@@ -46,6 +117,37 @@ impl From<u8> for OpCode {
             18 => OpCode::Pop,
             19 => OpCode::DefineGlobal,
             20 => OpCode::GetGlobal,
+            21 => OpCode::SetGlobal,
+            22 => OpCode::GetLocal,
+            23 => OpCode::SetLocal,
+            24 => OpCode::Jump,
+            25 => OpCode::JumpIfFalse,
+            26 => OpCode::Loop,
+            27 => OpCode::Call,
+            28 => OpCode::Closure,
+            29 => OpCode::GetUpvalue,
+            30 => OpCode::SetUpvalue,
+            31 => OpCode::CloseUpvalue,
+            32 => OpCode::Class,
+            33 => OpCode::GetProperty,
+            34 => OpCode::SetProperty,
+            35 => OpCode::Method,
+            36 => OpCode::Invoke,
+            37 => OpCode::Inherit,
+            38 => OpCode::GetSuper,
+            39 => OpCode::SuperInvoke,
+            40 => OpCode::ConstantLong,
+            41 => OpCode::DefineGlobalLong,
+            42 => OpCode::GetGlobalLong,
+            43 => OpCode::SetGlobalLong,
+            44 => OpCode::ClosureLong,
+            45 => OpCode::ClassLong,
+            46 => OpCode::GetPropertyLong,
+            47 => OpCode::SetPropertyLong,
+            48 => OpCode::MethodLong,
+            49 => OpCode::InvokeLong,
+            50 => OpCode::GetSuperLong,
+            51 => OpCode::SuperInvokeLong,
             _ => unimplemented!("Invalid OpCode from u8"),
         }
     }
@@ -75,6 +177,37 @@ impl From<OpCode> for u8 {
             OpCode::Pop => 18,
             OpCode::DefineGlobal => 19,
             OpCode::GetGlobal => 20,
+            OpCode::SetGlobal => 21,
+            OpCode::GetLocal => 22,
+            OpCode::SetLocal => 23,
+            OpCode::Jump => 24,
+            OpCode::JumpIfFalse => 25,
+            OpCode::Loop => 26,
+            OpCode::Call => 27,
+            OpCode::Closure => 28,
+            OpCode::GetUpvalue => 29,
+            OpCode::SetUpvalue => 30,
+            OpCode::CloseUpvalue => 31,
+            OpCode::Class => 32,
+            OpCode::GetProperty => 33,
+            OpCode::SetProperty => 34,
+            OpCode::Method => 35,
+            OpCode::Invoke => 36,
+            OpCode::Inherit => 37,
+            OpCode::GetSuper => 38,
+            OpCode::SuperInvoke => 39,
+            OpCode::ConstantLong => 40,
+            OpCode::DefineGlobalLong => 41,
+            OpCode::GetGlobalLong => 42,
+            OpCode::SetGlobalLong => 43,
+            OpCode::ClosureLong => 44,
+            OpCode::ClassLong => 45,
+            OpCode::GetPropertyLong => 46,
+            OpCode::SetPropertyLong => 47,
+            OpCode::MethodLong => 48,
+            OpCode::InvokeLong => 49,
+            OpCode::GetSuperLong => 50,
+            OpCode::SuperInvokeLong => 51,
             // _ => unimplemented!("Invalid OpCode from opcode")
         }
     }
@@ -0,0 +1,155 @@
+use bytecode_lox::Vm;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{stdout, BufRead, BufReader, Write};
+use std::path::PathBuf;
+
+const HISTORY_FILE: &str = ".bytecode_lox_history";
+const HISTORY_LIMIT: usize = 1000;
+
+const HELP: &str = "\
+Enter Lox declarations and statements. Input continues on the next line
+while a block, call or string is left open; an empty line runs it anyway.
+An expression without a trailing ';' has its value printed.
+
+Commands:
+  :help            Show this help
+  :globals         List every global variable and its value
+  :disasm <name>   Disassemble a function or class's methods, given a
+                   global name or a property path like 'a.b'; runs no code
+  :load <file>     Run a script in the current session
+  :reset           Forget every global
+  :history         Show previous input
+  :quit            Leave the REPL
+";
+
+pub fn repl(vm: &mut Vm) {
+    vm.set_echo_expressions(true);
+    let mut history = History::load();
+    let stdin = io::stdin();
+    let mut input = String::new();
+
+    loop {
+        print!("{}", if input.is_empty() { "> " } else { ". " });
+        let _ = stdout().flush();
+
+        let mut line = String::new();
+        match stdin.lock().read_line(&mut line) {
+            Ok(0) | Err(_) => break,
+            Ok(_) => {}
+        }
+        let line = line.trim_end_matches(['\n', '\r']);
+
+        if input.is_empty() {
+            let command = line.trim();
+            if command.is_empty() {
+                continue;
+            }
+            if let Some(command) = command.strip_prefix(':') {
+                history.add(line);
+                if !run_command(vm, command, &history) {
+                    break;
+                }
+                continue;
+            }
+        }
+
+        input.push_str(line);
+        input.push('\n');
+        if !line.trim().is_empty() && vm.is_incomplete(&input) {
+            continue;
+        }
+
+        history.add(input.trim_end());
+        // Errors are reported through the diagnostics sink, which main
+        // points at stderr.
+        let _ = vm.interpret(&input);
+        input.clear();
+    }
+}
+
+/// Runs a `:` command, returning false when the REPL should exit.
+fn run_command(vm: &mut Vm, command: &str, history: &History) -> bool {
+    let (name, argument) = match command.split_once(char::is_whitespace) {
+        Some((name, argument)) => (name, argument.trim()),
+        None => (command, ""),
+    };
+
+    match name {
+        "help" => print!("{HELP}"),
+        "globals" => {
+            let mut globals: Vec<_> = vm.globals().collect();
+            globals.sort_by_key(|(name, _)| *name);
+            for (name, value) in globals {
+                println!("{name} = {value}");
+            }
+        }
+        "disasm" if argument.is_empty() => eprintln!("Usage: :disasm <name>"),
+        "disasm" => {
+            if let Err(message) = vm.disassemble(argument, &mut stdout()) {
+                eprintln!("{message}");
+            }
+        }
+        "load" if argument.is_empty() => eprintln!("Usage: :load <file>"),
+        "load" => match std::fs::read_to_string(argument) {
+            Ok(source) => {
+                let _ = vm.interpret(&source);
+            }
+            Err(error) => eprintln!("Could not read '{argument}': {error}"),
+        },
+        "reset" => vm.reset(),
+        "history" => {
+            for entry in &history.entries {
+                println!("{entry}");
+            }
+        }
+        "quit" | "exit" => return false,
+        _ => eprintln!("Unknown command ':{name}', try :help."),
+    }
+    true
+}
+
+/// Previous input, kept in a dotfile in the home directory across sessions.
+struct History {
+    entries: Vec<String>,
+    file: Option<File>,
+}
+
+impl History {
+    fn load() -> Self {
+        let Some(path) = history_path() else {
+            return Self {
+                entries: Vec::new(),
+                file: None,
+            };
+        };
+
+        let mut entries: Vec<String> = match File::open(&path) {
+            Ok(file) => BufReader::new(file).lines().map_while(Result::ok).collect(),
+            Err(_) => Vec::new(),
+        };
+        if entries.len() > HISTORY_LIMIT {
+            // Rewrite the file too, which is only ever appended to otherwise.
+            entries.drain(..entries.len() - HISTORY_LIMIT);
+            let trimmed: String = entries.iter().map(|entry| format!("{entry}\n")).collect();
+            let _ = std::fs::write(&path, trimmed);
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .ok();
+        Self { entries, file }
+    }
+
+    fn add(&mut self, entry: &str) {
+        if let Some(file) = &mut self.file {
+            let _ = writeln!(file, "{entry}");
+        }
+        self.entries.extend(entry.lines().map(str::to_string));
+    }
+}
+
+fn history_path() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(HISTORY_FILE))
+}
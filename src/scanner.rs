@@ -1,4 +1,4 @@
-use crate::token::Token;
+use crate::token::{Span, Token};
 use crate::token_type::TokenType;
 
 pub struct Scanner {
@@ -6,6 +6,13 @@ pub struct Scanner {
     start: usize,
     current: usize,
     line: usize,
+    /// Index of the first character of the current line.
+    line_start: usize,
+    current_byte: usize,
+    /// Position of the token being scanned, filled in as it is finished.
+    span: Span,
+    /// Whether the source ended inside a string.
+    unterminated: bool,
 }
 
 impl Scanner {
@@ -15,12 +22,27 @@ impl Scanner {
             start: 0,
             current: 0,
             line: 1,
+            line_start: 0,
+            current_byte: 0,
+            span: Span::default(),
+            unterminated: false,
         }
     }
 
+    /// Whether the source ended inside a token, so more input could complete it.
+    pub fn is_unterminated(&self) -> bool {
+        self.unterminated
+    }
+
     pub fn scan_token(&mut self) -> Token {
         self.skip_whitespace();
         self.start = self.current;
+        self.span = Span {
+            line: self.line,
+            column: self.current - self.line_start + 1,
+            start: self.current_byte,
+            end: self.current_byte,
+        };
 
         if self.is_at_end() {
             return self.make_token(TokenType::Eof);
@@ -86,17 +108,30 @@ impl Scanner {
         Token::new(
             ttype,
             self.source[self.start..self.current].iter().collect(),
-            self.line,
+            self.token_span(),
         )
     }
 
     fn error_token(&self, message: &str) -> Token {
-        Token::new(TokenType::Error, message.to_string(), self.line)
+        Token::new(TokenType::Error, message.to_string(), self.token_span())
+    }
+
+    fn token_span(&self) -> Span {
+        Span {
+            end: self.current_byte,
+            ..self.span
+        }
     }
 
     fn advance(&mut self) -> char {
+        let c = self.source[self.current];
         self.current += 1;
-        self.source[self.current - 1]
+        self.current_byte += c.len_utf8();
+        if c == '\n' {
+            self.line += 1;
+            self.line_start = self.current;
+        }
+        c
     }
 
     fn is_match(&mut self, expected: char) -> bool {
@@ -107,27 +142,19 @@ impl Scanner {
         if self.source[self.current] != expected {
             return false;
         }
-        self.current += 1;
+        self.advance();
         true
     }
 
     fn skip_whitespace(&mut self) {
         loop {
             match self.peek() {
-                ' ' | '\r' | '\t' => {
-                    self.advance();
-                }
-                '\n' => {
-                    self.line += 1;
+                ' ' | '\r' | '\t' | '\n' => {
                     self.advance();
                 }
-                '/' => {
-                    if '/' == self.peek_next() {
-                        while self.peek() != '\n' && !self.is_at_end() {
-                            self.advance();
-                        }
-                    } else {
-                        return;
+                '/' if '/' == self.peek_next() => {
+                    while self.peek() != '\n' && !self.is_at_end() {
+                        self.advance();
                     }
                 }
                 _ => {
@@ -146,7 +173,7 @@ impl Scanner {
     }
 
     fn peek_next(&self) -> char {
-        if self.is_at_end() {
+        if self.current + 1 >= self.source.len() {
             '\0'
         } else {
             self.source[self.current + 1]
@@ -155,13 +182,11 @@ impl Scanner {
 
     fn string(&mut self) -> Token {
         while self.peek() != '"' && !self.is_at_end() {
-            if self.peek() == '\n' {
-                self.line += 1;
-            }
             self.advance();
         }
 
         if self.is_at_end() {
+            self.unterminated = true;
             self.error_token("Unterminated string.")
         } else {
             self.advance();
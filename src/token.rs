@@ -2,11 +2,21 @@ use crate::token_type::TokenType;
 use std::fmt;
 use std::fmt::Formatter;
 
+/// Where a token sits in the source. Lines and columns count from 1, columns
+/// in characters, and `start..end` is the byte range the token covers.
+#[derive(Debug, Default, Copy, Clone, Ord, PartialOrd, Eq, PartialEq)]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
 #[derive(Debug, Ord, PartialOrd, Eq, PartialEq)]
 pub struct Token {
     pub ttype: TokenType,
     pub lexeme: String,
-    pub line: usize,
+    pub span: Span,
 }
 
 impl Default for Token {
@@ -14,7 +24,7 @@ impl Default for Token {
         Self {
             ttype: TokenType::Undefined,
             lexeme: "".to_string(),
-            line: 0,
+            span: Span::default(),
         }
     }
 }
@@ -24,17 +34,17 @@ impl Clone for Token {
         Self {
             ttype: self.ttype,
             lexeme: self.lexeme.clone(),
-            line: self.line,
+            span: self.span,
         }
     }
 }
 
 impl Token {
-    pub fn new(ttype: TokenType, lexeme: String, line: usize) -> Token {
+    pub fn new(ttype: TokenType, lexeme: String, span: Span) -> Token {
         Token {
             ttype,
             lexeme,
-            line,
+            span,
         }
     }
     pub fn is(&self, ttype: TokenType) -> bool {
@@ -60,6 +70,6 @@ impl Token {
 
 impl fmt::Display for Token {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        write!(f, "{} {:?}  {}", self.line, self.ttype, self.lexeme)
+        write!(f, "{} {:?}  {}", self.span.line, self.ttype, self.lexeme)
     }
 }
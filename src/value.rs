@@ -1,9 +1,11 @@
 use crate::object::Object;
-use crate::vm::InterpretResult;
+use std::cmp::Ordering;
 use std::fmt::{Display, Formatter};
+use std::io;
+use std::io::Write;
 use std::ops::{Add, Div, Mul, Neg, Sub};
 
-#[derive(Debug, Clone, PartialOrd, PartialEq)]
+#[derive(Debug, Copy, Clone, PartialOrd, PartialEq)]
 pub enum Value {
     Boolean(bool),
     Number(f64),
@@ -12,16 +14,54 @@ pub enum Value {
 }
 
 impl Value {
-    pub fn is_number(&self) -> bool {
-        matches!(self, Value::Number(_))
+    pub fn is_falsy(&self) -> bool {
+        matches!(self, Value::Nil | Value::Boolean(false))
     }
 
-    pub fn is_string(&self) -> bool {
-        matches!(self, Value::Obj(Object::Str(_)))
+    pub fn compare(&self, rhs: &Value) -> Result<Option<Ordering>, String> {
+        match (self, rhs) {
+            (Value::Number(left), Value::Number(right)) => Ok(left.partial_cmp(right)),
+            (Value::Obj(Object::Str(left)), Value::Obj(Object::Str(right))) => {
+                Ok(left.as_str().partial_cmp(right.as_str()))
+            }
+            _ => Err("Operands must be two numbers or two strings.".to_string()),
+        }
     }
+}
 
-    pub fn is_falsy(&self) -> bool {
-        matches!(self, Value::Nil | Value::Boolean(false))
+/// A Lox value copied out of the VM. Unlike the VM's own values it owns its
+/// data, so it stays valid after a garbage collection or the VM is dropped.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LoxValue {
+    Nil,
+    Boolean(bool),
+    Number(f64),
+    String(String),
+    /// Any other object, such as a function or an instance, by its printed
+    /// form. It can't be passed back into the VM.
+    Object(String),
+}
+
+impl Value {
+    pub fn to_lox_value(self) -> LoxValue {
+        match self {
+            Value::Boolean(v) => LoxValue::Boolean(v),
+            Value::Number(v) => LoxValue::Number(v),
+            Value::Nil => LoxValue::Nil,
+            Value::Obj(Object::Str(s)) => LoxValue::String(s.chars.clone()),
+            Value::Obj(object) => LoxValue::Object(object.to_string()),
+        }
+    }
+}
+
+impl Display for LoxValue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LoxValue::Nil => write!(f, "nil"),
+            LoxValue::Boolean(v) => write!(f, "{v}"),
+            LoxValue::Number(v) => write!(f, "{v}"),
+            LoxValue::String(s) | LoxValue::Object(s) => write!(f, "{s}"),
+        }
     }
 }
 
@@ -44,72 +84,126 @@ impl Display for Value {
     }
 }
 
+impl From<f64> for LoxValue {
+    fn from(value: f64) -> Self {
+        LoxValue::Number(value)
+    }
+}
+
+impl From<bool> for LoxValue {
+    fn from(value: bool) -> Self {
+        LoxValue::Boolean(value)
+    }
+}
+
+impl From<()> for LoxValue {
+    fn from(_: ()) -> Self {
+        LoxValue::Nil
+    }
+}
+
+impl From<String> for LoxValue {
+    fn from(value: String) -> Self {
+        LoxValue::String(value)
+    }
+}
+
+impl From<&str> for LoxValue {
+    fn from(value: &str) -> Self {
+        LoxValue::String(value.to_string())
+    }
+}
+
+impl TryFrom<LoxValue> for f64 {
+    type Error = String;
+
+    fn try_from(value: LoxValue) -> Result<Self, Self::Error> {
+        match value {
+            LoxValue::Number(v) => Ok(v),
+            _ => Err(format!("Expected a number but got {value}.")),
+        }
+    }
+}
+
+impl TryFrom<LoxValue> for bool {
+    type Error = String;
+
+    fn try_from(value: LoxValue) -> Result<Self, Self::Error> {
+        match value {
+            LoxValue::Boolean(v) => Ok(v),
+            _ => Err(format!("Expected a boolean but got {value}.")),
+        }
+    }
+}
+
+impl TryFrom<LoxValue> for String {
+    type Error = String;
+
+    fn try_from(value: LoxValue) -> Result<Self, Self::Error> {
+        match value {
+            LoxValue::String(v) => Ok(v),
+            _ => Err(format!("Expected a string but got {value}.")),
+        }
+    }
+}
+
 impl Sub for Value {
-    type Output = Value;
+    type Output = Result<Value, String>;
 
     fn sub(self, rhs: Self) -> Self::Output {
         match (self, rhs) {
-            (Value::Number(left), Value::Number(right)) => Value::Number(left - right),
-            _ => panic!("Only support numbers and numbers."),
+            (Value::Number(left), Value::Number(right)) => Ok(Value::Number(left - right)),
+            _ => Err("Operands must be numbers.".to_string()),
         }
     }
 }
 
 impl Div for Value {
-    type Output = Value;
+    type Output = Result<Value, String>;
 
     fn div(self, rhs: Self) -> Self::Output {
         match (self, rhs) {
             (Value::Number(left), Value::Number(right)) => {
                 if right == 0 as f64 {
-                    panic!("Can't divide by zero.")
+                    Err("Can't divide by zero.".to_string())
                 } else {
-                    Value::Number(left / right)
+                    Ok(Value::Number(left / right))
                 }
             }
-            _ => panic!("Only support numbers and numbers."),
+            _ => Err("Operands must be numbers.".to_string()),
         }
     }
 }
 
 impl Mul for Value {
-    type Output = Value;
+    type Output = Result<Value, String>;
 
     fn mul(self, rhs: Self) -> Self::Output {
         match (self, rhs) {
-            (Value::Number(left), Value::Number(right)) => Value::Number(left * right),
-            _ => panic!("Only support numbers and numbers."),
+            (Value::Number(left), Value::Number(right)) => Ok(Value::Number(left * right)),
+            _ => Err("Operands must be numbers.".to_string()),
         }
     }
 }
 
 impl Add for Value {
-    type Output = Value;
+    type Output = Result<Value, String>;
 
     fn add(self, rhs: Self) -> Self::Output {
         match (self, rhs) {
-            (Value::Number(left), Value::Number(right)) => Value::Number(left + right),
-            (Value::Obj(Object::Str(left)), Value::Obj(Object::Str(right))) => {
-                Value::Obj(Object::Str(format!("{}{}", left, right)))
-            }
-            (Value::Number(left), Value::Obj(Object::Str(right))) => {
-                Value::Obj(Object::Str(format!("{}{}", left, right)))
-            }
-            (Value::Obj(Object::Str(left)), Value::Number(right)) => {
-                Value::Obj(Object::Str(format!("{}{}", left, right)))
-            }
-            _ => panic!("Only support numbers and numbers."),
+            (Value::Number(left), Value::Number(right)) => Ok(Value::Number(left + right)),
+            _ => Err("Operands must be numbers or strings.".to_string()),
         }
     }
 }
 
 impl Neg for Value {
-    type Output = Value;
+    type Output = Result<Value, String>;
 
     fn neg(self) -> Self::Output {
         match self {
-            Value::Number(v) => Value::Number(-v),
-            _ => panic!("Only support numbers."),
+            Value::Number(v) => Ok(Value::Number(-v)),
+            _ => Err("Operand must be a number.".to_string()),
         }
     }
 }
@@ -130,19 +224,15 @@ impl ValueArray {
         count
     }
 
-    pub fn free(&mut self) {
-        self.values.clear();
+    pub fn write_value(&self, out: &mut dyn Write, which: usize) -> io::Result<()> {
+        write!(out, "{}", self.values[which])
     }
 
-    pub fn print_value(&self, which: usize) {
-        print!("{}", self.values[which]);
+    pub fn iter(&self) -> impl Iterator<Item = &Value> {
+        self.values.iter()
     }
 
-    pub fn read_value(&self, which: usize) -> Result<Value, InterpretResult> {
-        if let Some(value) = self.values.get(which) {
-            Ok(value.clone())
-        } else {
-            Err(InterpretResult::CompileError)
-        }
+    pub fn read_value(&self, which: usize) -> Option<Value> {
+        self.values.get(which).copied()
     }
 }
@@ -1,93 +1,623 @@
 use crate::chunk::Chunk;
-use crate::compiler::Compiler;
-use crate::object::Object;
+use crate::compiler::{Compiler, DEFAULT_MAX_ERRORS};
+use crate::error::{
+    source_line, Diagnostic, ErrorKind, LoxError, StackFrame, Warning, WarningKind,
+};
+use crate::gc::{Gc, Heap, Trace};
+use crate::object::{
+    BoundMethod, Class, Closure, Function, Instance, LoxString, Native, Object, Upvalue,
+};
 use crate::opcode::OpCode;
-use crate::value::Value;
-use std::collections::HashMap;
+use crate::suggest::closest;
+use crate::value::{LoxValue, Value};
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::io::{BufRead, BufReader, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
 
-pub struct VM<'a> {
-    chunk: &'a mut Chunk,
+const FRAMES_MAX: usize = 64;
+
+struct CallFrame {
+    closure: Gc<Closure>,
     ip: usize,
+    slots: usize,
+}
+
+pub struct Vm {
+    frames: Vec<CallFrame>,
     stack: Vec<Value>,
-    globals: HashMap<String, Value>,
+    globals: HashMap<Gc<LoxString>, Value>,
+    open_upvalues: Vec<Gc<RefCell<Upvalue>>>,
+    heap: Heap,
+    init_string: Gc<LoxString>,
+    output: Box<dyn Write>,
+    diagnostics: Box<dyn Write>,
+    trace: Box<dyn Write>,
+    input: Box<dyn BufRead>,
+    trace_execution: bool,
+    print_code: bool,
+    echo_expressions: bool,
+    max_errors: Option<usize>,
+    enabled_warnings: HashSet<WarningKind>,
+    warning_handler: Option<WarningHandler>,
+    args: Option<Vec<String>>,
 }
 
-impl<'a> VM<'a> {
-    pub fn new(chunk: &'a mut Chunk) -> Self {
-        Self {
-            chunk,
-            ip: 0,
+pub type WarningHandler = Box<dyn FnMut(&Warning)>;
+
+impl Vm {
+    pub fn new() -> Self {
+        let mut heap = Heap::new();
+        let init_string = heap.intern("init".to_string());
+        let mut vm = Self {
+            frames: Vec::new(),
             stack: Vec::new(),
             globals: HashMap::new(),
+            open_upvalues: Vec::new(),
+            heap,
+            init_string,
+            output: Box::new(io::stdout()),
+            diagnostics: Box::new(io::sink()),
+            trace: Box::new(io::stdout()),
+            input: Box::new(BufReader::new(io::stdin())),
+            trace_execution: false,
+            print_code: false,
+            echo_expressions: false,
+            max_errors: Some(DEFAULT_MAX_ERRORS),
+            enabled_warnings: WarningKind::ALL.into_iter().collect(),
+            warning_handler: None,
+            args: None,
+        };
+        vm.define_builtins();
+        vm
+    }
+
+    fn define_builtins(&mut self) {
+        self.define_native("clock", 0, clock_native);
+        if let Some(args) = self.args.clone() {
+            self.define_args(args);
+        }
+    }
+
+    /// Forgets every global, including natives defined by the embedder, and
+    /// restores the built-in ones and `args`.
+    pub fn reset(&mut self) {
+        self.reset_stack();
+        self.globals.clear();
+        self.define_builtins();
+    }
+
+    pub fn define_native<F>(&mut self, name: &str, arity: usize, function: F)
+    where
+        F: Fn(&[LoxValue]) -> Result<LoxValue, String> + 'static,
+    {
+        let native = self.alloc(Native::new(name.to_string(), arity, Box::new(function)));
+        self.stack.push(Value::Obj(Object::Native(native)));
+        let name = self.intern(name.to_string());
+        self.globals
+            .insert(name, Value::Obj(Object::Native(native)));
+        self.pop();
+    }
+
+    /// Sink for the output of `print` statements.
+    pub fn set_output(&mut self, output: Box<dyn Write>) {
+        self.output = output;
+    }
+
+    /// Sink that compile and runtime errors are reported to, along with
+    /// warnings when no warning handler is set. Nothing is written by
+    /// default, leaving errors to be read from the returned `LoxError`.
+    pub fn set_diagnostics(&mut self, diagnostics: Box<dyn Write>) {
+        self.diagnostics = diagnostics;
+    }
+
+    /// Sink for disassembly and execution traces.
+    pub fn set_trace(&mut self, trace: Box<dyn Write>) {
+        self.trace = trace;
+    }
+
+    /// Prints the stack and each instruction to the trace sink as it runs.
+    pub fn set_trace_execution(&mut self, enabled: bool) {
+        self.trace_execution = enabled;
+    }
+
+    /// Disassembles every function to the trace sink once it is compiled.
+    pub fn set_print_code(&mut self, enabled: bool) {
+        self.print_code = enabled;
+    }
+
+    /// Prints the value of a trailing expression statement written without a
+    /// `;`, as a REPL does.
+    pub fn set_echo_expressions(&mut self, enabled: bool) {
+        self.echo_expressions = enabled;
+    }
+
+    /// Stops compiling a script once this many errors have been found, or
+    /// never when `None`.
+    pub fn set_max_errors(&mut self, max_errors: Option<usize>) {
+        self.max_errors = max_errors;
+    }
+
+    /// Turns one kind of compiler warning on or off. All are on by default.
+    pub fn set_warning(&mut self, kind: WarningKind, enabled: bool) {
+        if enabled {
+            self.enabled_warnings.insert(kind);
+        } else {
+            self.enabled_warnings.remove(&kind);
+        }
+    }
+
+    /// Called with each warning for a script that compiled, before it runs,
+    /// instead of reporting it to the diagnostics sink.
+    pub fn set_warning_handler(&mut self, handler: WarningHandler) {
+        self.warning_handler = Some(handler);
+    }
+
+    pub fn set_input(&mut self, input: Box<dyn BufRead>) {
+        self.input = input;
+    }
+
+    pub fn input(&mut self) -> &mut dyn BufRead {
+        &mut *self.input
+    }
+
+    /// Exposes command line arguments to scripts as an `args` global, with the
+    /// number of arguments in `args.count` and each one returned by `args.get(i)`.
+    pub fn set_args(&mut self, args: &[String]) {
+        self.args = Some(args.to_vec());
+        self.define_args(args.to_vec());
+    }
+
+    fn define_args(&mut self, strings: Vec<String>) {
+        let base = self.stack.len();
+        let length = strings.len();
+        let get = move |args: &[LoxValue]| match args[0] {
+            LoxValue::Number(index) if index >= 0.0 && index.fract() == 0.0 => strings
+                .get(index as usize)
+                .map(|arg| LoxValue::String(arg.clone()))
+                .ok_or_else(|| "Argument index out of range.".to_string()),
+            _ => Err("Argument index must be a non-negative integer.".to_string()),
+        };
+        let native = self.alloc(Native::new("get".to_string(), 1, Box::new(get)));
+        self.stack.push(Value::Obj(Object::Native(native)));
+
+        let name = self.intern("Args".to_string());
+        self.stack.push(Value::Obj(Object::Str(name)));
+        let class = self.alloc(RefCell::new(Class::new(name)));
+        self.stack.push(Value::Obj(Object::Class(class)));
+        let instance = self.alloc(RefCell::new(Instance::new(class)));
+        self.stack.push(Value::Obj(Object::Instance(instance)));
+
+        let count = self.intern("count".to_string());
+        self.stack.push(Value::Obj(Object::Str(count)));
+        let get = self.intern("get".to_string());
+        {
+            let mut instance = instance.borrow_mut();
+            instance.fields.insert(count, Value::Number(length as f64));
+            instance
+                .fields
+                .insert(get, Value::Obj(Object::Native(native)));
+        }
+
+        let global = self.intern("args".to_string());
+        self.globals
+            .insert(global, Value::Obj(Object::Instance(instance)));
+        self.stack.truncate(base);
+    }
+
+    pub fn globals(&self) -> impl Iterator<Item = (&str, LoxValue)> {
+        self.globals
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.to_lox_value()))
+    }
+
+    pub fn get_global(&self, name: &str) -> Option<LoxValue> {
+        let name = self.heap.find_interned(name)?;
+        self.globals.get(&name).map(|value| value.to_lox_value())
+    }
+
+    /// Defines or replaces a global. Fails for `LoxValue::Object`, as objects
+    /// can only be copied out of the VM.
+    pub fn set_global<V: Into<LoxValue>>(&mut self, name: &str, value: V) -> Result<(), String> {
+        let value = self.import(value.into())?;
+        self.stack.push(value);
+        let name = self.intern(name.to_string());
+        let value = self.pop();
+        self.globals.insert(name, value);
+        Ok(())
+    }
+
+    /// Turns a value from the embedder into one the VM can use. Like any new
+    /// object, a string made here must be rooted before the next allocation.
+    fn import(&mut self, value: LoxValue) -> Result<Value, String> {
+        match value {
+            LoxValue::Nil => Ok(Value::Nil),
+            LoxValue::Boolean(v) => Ok(Value::Boolean(v)),
+            LoxValue::Number(v) => Ok(Value::Number(v)),
+            LoxValue::String(s) => Ok(Value::Obj(Object::Str(self.intern(s)))),
+            LoxValue::Object(description) => Err(format!(
+                "Can't pass {description} into the VM, only nil, booleans, numbers and strings."
+            )),
+        }
+    }
+
+    /// Compiles `source` without running it, reporting only compile errors
+    /// and warnings.
+    pub fn check(&mut self, source: &str) -> Result<(), LoxError> {
+        let result = self.compile(source);
+        self.report(result)?;
+        Ok(())
+    }
+
+    /// Whether `source` stops in the middle of a declaration or string, so a
+    /// REPL should read more lines before running it.
+    pub fn is_incomplete(&mut self, source: &str) -> bool {
+        let mut compiler = Compiler::new(&mut self.heap, None);
+        compiler.set_echo(self.echo_expressions);
+        match compiler.compile(source) {
+            Ok(_) => false,
+            Err(error) => error
+                .diagnostics
+                .iter()
+                .all(|diagnostic| diagnostic.incomplete),
         }
     }
 
-    pub fn reset_stack(&mut self) {
+    fn reset_stack(&mut self) {
         self.stack.clear();
+        self.frames.clear();
+        self.open_upvalues.clear();
     }
 
     fn free(&mut self) {
         self.stack.clear();
-        self.chunk.free();
-        self.ip = 0;
+        self.frames.clear();
+        self.open_upvalues.clear();
+    }
+
+    pub fn interpret(&mut self, source: &str) -> Result<(), LoxError> {
+        let result = self
+            .compile(source)
+            .and_then(|function| self.execute(function));
+        self.report(result)?;
+        Ok(())
     }
 
-    pub fn interpret(&mut self, source: &str) -> Result<(), InterpretResult> {
-        let mut compiler = Compiler::new(self.chunk);
-        compiler.compile(source)?;
-        self.ip = 0;
-        let result = self.run();
+    /// Evaluates a single expression and returns a copy of its value.
+    pub fn evaluate(&mut self, expression: &str) -> Result<LoxValue, LoxError> {
+        let result = self
+            .compiler()
+            .compile_expression(expression)
+            .and_then(|function| self.execute(function));
+        self.report(result)
+    }
+
+    /// Writes an error to the diagnostics sink before handing it back.
+    fn report<T>(&mut self, result: Result<T, LoxError>) -> Result<T, LoxError> {
+        if let Err(error) = &result {
+            let _ = error.write_report(&mut *self.diagnostics);
+        }
+        result
+    }
+
+    /// Disassembles the function, method or class methods that a global
+    /// name or a path like `a.b` refers to. Nothing is run, so paths can't
+    /// contain calls or other expressions.
+    pub fn disassemble(&mut self, path: &str, out: &mut dyn Write) -> Result<(), String> {
+        let _ = match self.resolve(path)? {
+            Value::Obj(Object::Function(function)) => function.chunk.disassemble(out, function),
+            Value::Obj(Object::Closure(closure)) => {
+                let function = closure.function;
+                function.chunk.disassemble(out, function)
+            }
+            Value::Obj(Object::BoundMethod(bound)) => {
+                let function = bound.method.function;
+                function.chunk.disassemble(out, function)
+            }
+            Value::Obj(Object::Class(class)) => {
+                let class = class.borrow();
+                let mut methods: Vec<_> = class.methods.iter().collect();
+                methods.sort_by_key(|(name, _)| name.as_str());
+                methods.into_iter().try_for_each(|(name, method)| {
+                    let name = format!("{}.{}", class.name, name);
+                    method.function.chunk.disassemble(out, name)
+                })
+            }
+            value => {
+                return Err(format!(
+                    "'{path}' is {}, not a function, method or class.",
+                    value.to_lox_value()
+                ))
+            }
+        };
+        Ok(())
+    }
+
+    /// Looks up a global and then each property in a path like `a.b.c`.
+    fn resolve(&self, path: &str) -> Result<Value, String> {
+        let mut names = path.split('.').map(str::trim);
+        let is_identifier = |name: &str| {
+            name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+                && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        };
+        if !names.clone().all(is_identifier) {
+            return Err(format!(
+                "Expect a variable name or a path like 'a.b' but got '{path}'."
+            ));
+        }
+
+        let global = names.next().unwrap();
+        let mut value = self
+            .heap
+            .find_interned(global)
+            .and_then(|name| self.globals.get(&name).copied())
+            .ok_or_else(|| format!("Undefined variable '{global}'."))?;
+        for name in names {
+            let key = self.heap.find_interned(name);
+            let property = match value {
+                Value::Obj(Object::Instance(instance)) => {
+                    let instance = instance.borrow();
+                    key.and_then(|key| {
+                        instance.fields.get(&key).copied().or_else(|| {
+                            let method = instance.class.borrow().methods.get(&key).copied();
+                            method.map(|method| Value::Obj(Object::Closure(method)))
+                        })
+                    })
+                }
+                Value::Obj(Object::Class(class)) => {
+                    let method = key.and_then(|key| class.borrow().methods.get(&key).copied());
+                    method.map(|method| Value::Obj(Object::Closure(method)))
+                }
+                _ => return Err("Only instances and classes have properties.".to_string()),
+            };
+            value = property.ok_or_else(|| format!("Undefined property '{name}'."))?;
+        }
+        Ok(value)
+    }
+
+    fn compiler(&mut self) -> Compiler<'_> {
+        let trace: Option<&mut dyn Write> = if self.print_code {
+            Some(&mut *self.trace)
+        } else {
+            None
+        };
+        let mut compiler = Compiler::new(&mut self.heap, trace);
+        compiler.set_echo(self.echo_expressions);
+        compiler.set_max_errors(self.max_errors);
+        for kind in WarningKind::ALL {
+            compiler.set_warning(kind, self.enabled_warnings.contains(&kind));
+        }
+        compiler
+    }
+
+    fn compile(&mut self, source: &str) -> Result<Gc<Function>, LoxError> {
+        let mut compiler = self.compiler();
+        let result = compiler.compile(source);
+        let warnings = compiler.take_warnings();
+        match self.warning_handler.as_mut() {
+            Some(handler) => warnings.iter().for_each(handler),
+            None => {
+                for warning in &warnings {
+                    let _ = warning.write_report(&mut *self.diagnostics);
+                }
+            }
+        }
+        result
+    }
+
+    /// Runs a compiled script or expression. Its result is copied out before
+    /// the stack is freed, as nothing roots it after that.
+    fn execute(&mut self, function: Gc<Function>) -> Result<LoxValue, LoxError> {
+        self.stack.push(Value::Obj(Object::Function(function)));
+        let closure = self.alloc(Closure::new(function, Vec::new()));
+        self.pop();
+        self.stack.push(Value::Obj(Object::Closure(closure)));
+        self.call(closure, 0)?;
+        let result = self.run().map(Value::to_lox_value);
         self.free();
         result
     }
 
-    fn run(&mut self) -> Result<(), InterpretResult> {
+    fn run(&mut self) -> Result<Value, LoxError> {
         loop {
-            #[cfg(feature = "debug_trace_execution")]
-            {
-                print!("           ");
-                for slot in &self.stack {
-                    print!("[ {slot} ]");
-                }
-                println!();
-                let _ = &self.chunk.disassemble_instruction(self.ip);
+            if self.trace_execution {
+                let _ = self.trace_instruction();
             }
 
             let instruction = self.read_byte();
 
             match instruction {
-                OpCode::GetGlobal => {
-                    let name = self.read_constant()?.clone();
-                    if let Value::Obj(Object::Str(s)) = name {
-                        if let Some(value) = self.globals.get(s.as_str()) {
-                            self.stack.push(value.clone());
-                        } else {
-                            return self.runtime_error(&format!("Undefined variable '{}'.", s));
+                OpCode::Jump => {
+                    let offset = self.read_short();
+                    self.frame_mut().ip += offset as usize;
+                }
+                OpCode::JumpIfFalse => {
+                    let offset = self.read_short();
+                    if self.peek(0).is_falsy() {
+                        self.frame_mut().ip += offset as usize;
+                    }
+                }
+                OpCode::Loop => {
+                    let offset = self.read_short();
+                    self.frame_mut().ip -= offset as usize;
+                }
+                OpCode::GetLocal => {
+                    let slot = self.frame().slots + self.read_operand() as usize;
+                    self.stack.push(self.stack[slot]);
+                }
+                OpCode::SetLocal => {
+                    let slot = self.frame().slots + self.read_operand() as usize;
+                    self.stack[slot] = self.peek(0);
+                }
+                OpCode::GetUpvalue => {
+                    let slot = self.read_operand() as usize;
+                    let upvalue = self.frame().closure.upvalues[slot];
+                    let value = match *upvalue.borrow() {
+                        Upvalue::Open(index) => self.stack[index],
+                        Upvalue::Closed(value) => value,
+                    };
+                    self.stack.push(value);
+                }
+                OpCode::SetUpvalue => {
+                    let slot = self.read_operand() as usize;
+                    let upvalue = self.frame().closure.upvalues[slot];
+                    let value = self.peek(0);
+                    match &mut *upvalue.borrow_mut() {
+                        Upvalue::Open(index) => self.stack[*index] = value,
+                        Upvalue::Closed(closed) => *closed = value,
+                    };
+                }
+                OpCode::Closure | OpCode::ClosureLong => {
+                    let constant = self.read_constant(instruction.is_long())?;
+                    if let Value::Obj(Object::Function(function)) = constant {
+                        let mut upvalues = Vec::with_capacity(function.upvalue_count);
+                        for _ in 0..function.upvalue_count {
+                            let is_local = self.read_operand() == 1;
+                            let index = self.read_operand() as usize;
+                            if is_local {
+                                let slot = self.frame().slots + index;
+                                upvalues.push(self.capture_upvalue(slot));
+                            } else {
+                                upvalues.push(self.frame().closure.upvalues[index]);
+                            }
                         }
+                        let closure = self.alloc(Closure::new(function, upvalues));
+                        self.stack.push(Value::Obj(Object::Closure(closure)));
                     } else {
-                        return self.runtime_error("Can`t read constant from table.");
+                        return self.runtime_error("Can`t read function from table.");
                     }
                 }
-                OpCode::DefineGlobal => {
-                    let name = self.read_constant()?.clone();
-                    if let Value::Obj(Object::Str(s)) = name {
-                        let p = self.pop();
-                        self.globals.insert(s, p.clone());
+                OpCode::CloseUpvalue => {
+                    self.close_upvalues(self.stack.len() - 1);
+                    self.pop();
+                }
+                OpCode::Class | OpCode::ClassLong => {
+                    let name = self.read_string(instruction.is_long())?;
+                    let class = self.alloc(RefCell::new(Class::new(name)));
+                    self.stack.push(Value::Obj(Object::Class(class)));
+                }
+                OpCode::GetProperty | OpCode::GetPropertyLong => {
+                    let instance = match self.peek(0) {
+                        Value::Obj(Object::Instance(instance)) => instance,
+                        _ => return self.runtime_error("Only instances have properties."),
+                    };
+                    let name = self.read_string(instruction.is_long())?;
+
+                    let field = instance.borrow().fields.get(&name).copied();
+                    if let Some(value) = field {
+                        self.pop();
+                        self.stack.push(value);
                     } else {
-                        return self.runtime_error("Unable to read constant from table.");
+                        let class = instance.borrow().class;
+                        self.bind_method(class, name)?;
+                    }
+                }
+                OpCode::SetProperty | OpCode::SetPropertyLong => {
+                    let instance = match self.peek(1) {
+                        Value::Obj(Object::Instance(instance)) => instance,
+                        _ => return self.runtime_error("Only instances have fields."),
+                    };
+                    let name = self.read_string(instruction.is_long())?;
+
+                    let value = self.pop();
+                    instance.borrow_mut().fields.insert(name, value);
+                    self.pop();
+                    self.stack.push(value);
+                }
+                OpCode::Method | OpCode::MethodLong => {
+                    let name = self.read_string(instruction.is_long())?;
+                    self.define_method(name)?;
+                }
+                OpCode::Invoke | OpCode::InvokeLong => {
+                    let method = self.read_string(instruction.is_long())?;
+                    let arg_count = self.read_operand() as usize;
+                    self.invoke(method, arg_count)?;
+                }
+                OpCode::Inherit => {
+                    let superclass = match self.peek(1) {
+                        Value::Obj(Object::Class(class)) => class,
+                        _ => return self.runtime_error("Superclass must be a class."),
+                    };
+                    if let Value::Obj(Object::Class(subclass)) = self.peek(0) {
+                        let methods = superclass.borrow().methods.clone();
+                        subclass.borrow_mut().methods.extend(methods);
+                    }
+                    self.pop();
+                }
+                OpCode::GetSuper | OpCode::GetSuperLong => {
+                    let name = self.read_string(instruction.is_long())?;
+                    match self.pop() {
+                        Value::Obj(Object::Class(superclass)) => {
+                            self.bind_method(superclass, name)?
+                        }
+                        _ => return self.runtime_error("Superclass must be a class."),
+                    }
+                }
+                OpCode::SuperInvoke | OpCode::SuperInvokeLong => {
+                    let method = self.read_string(instruction.is_long())?;
+                    let arg_count = self.read_operand() as usize;
+                    match self.pop() {
+                        Value::Obj(Object::Class(superclass)) => {
+                            self.invoke_from_class(superclass, method, arg_count)?
+                        }
+                        _ => return self.runtime_error("Superclass must be a class."),
+                    }
+                }
+                OpCode::Call => {
+                    let arg_count = self.read_operand() as usize;
+                    let callee = self.peek(arg_count);
+                    self.call_value(callee, arg_count)?;
+                }
+                OpCode::GetGlobal | OpCode::GetGlobalLong => {
+                    let offset = self.frame().ip - 1;
+                    let name = self.read_string(instruction.is_long())?;
+                    if let Some(value) = self.globals.get(&name) {
+                        self.stack.push(*value);
+                    } else {
+                        return self.undefined_variable(name, offset);
+                    }
+                }
+                OpCode::DefineGlobal | OpCode::DefineGlobalLong => {
+                    let name = self.read_string(instruction.is_long())?;
+                    let value = self.peek(0);
+                    self.globals.insert(name, value);
+                    self.pop();
+                }
+                OpCode::SetGlobal | OpCode::SetGlobalLong => {
+                    let offset = self.frame().ip - 1;
+                    let name = self.read_string(instruction.is_long())?;
+                    if self.globals.contains_key(&name) {
+                        let value = self.peek(0);
+                        self.globals.insert(name, value);
+                    } else {
+                        return self.undefined_variable(name, offset);
                     }
                 }
                 OpCode::Pop => {
                     self.pop();
                 }
                 OpCode::Print => {
-                    println!("{}", self.stack.pop().unwrap());
+                    let value = self.pop();
+                    if let Err(error) = writeln!(self.output, "{value}") {
+                        return self.runtime_error(&error);
+                    }
                 }
                 OpCode::Return => {
-                    return Ok(());
+                    let result = self.pop();
+                    let frame = self.frames.pop().unwrap();
+                    self.close_upvalues(frame.slots);
+                    if self.frames.is_empty() {
+                        self.pop();
+                        return Ok(result);
+                    }
+
+                    self.stack.truncate(frame.slots);
+                    self.stack.push(result);
                 }
-                OpCode::Constant => {
-                    let constant = self.read_constant()?;
+                OpCode::Constant | OpCode::ConstantLong => {
+                    let constant = self.read_constant(instruction.is_long())?;
                     self.stack.push(constant);
                 }
                 OpCode::Nil => self.stack.push(Value::Nil),
@@ -103,11 +633,34 @@ impl<'a> VM<'a> {
                     let a = self.pop();
                     self.stack.push(Value::Boolean(a != b));
                 }
-                OpCode::Greater => self.binary_op(|a, b| Value::Boolean(a > b))?,
-                OpCode::GreaterEqual => self.binary_op(|a, b| Value::Boolean(a >= b))?,
-                OpCode::Less => self.binary_op(|a, b| Value::Boolean(a < b))?,
-                OpCode::LessEqual => self.binary_op(|a, b| Value::Boolean(a <= b))?,
-                OpCode::Add => self.binary_op(|a, b| a + b)?,
+                OpCode::Greater => self.binary_op(|a, b| {
+                    let ordering = a.compare(&b)?;
+                    Ok(Value::Boolean(ordering == Some(Ordering::Greater)))
+                })?,
+                OpCode::GreaterEqual => self.binary_op(|a, b| {
+                    let ordering = a.compare(&b)?;
+                    Ok(Value::Boolean(matches!(
+                        ordering,
+                        Some(Ordering::Greater | Ordering::Equal)
+                    )))
+                })?,
+                OpCode::Less => self.binary_op(|a, b| {
+                    let ordering = a.compare(&b)?;
+                    Ok(Value::Boolean(ordering == Some(Ordering::Less)))
+                })?,
+                OpCode::LessEqual => self.binary_op(|a, b| {
+                    let ordering = a.compare(&b)?;
+                    Ok(Value::Boolean(matches!(
+                        ordering,
+                        Some(Ordering::Less | Ordering::Equal)
+                    )))
+                })?,
+                OpCode::Add => match (self.peek(1), self.peek(0)) {
+                    (Value::Obj(Object::Str(_)), _) | (_, Value::Obj(Object::Str(_))) => {
+                        self.concatenate()?
+                    }
+                    _ => self.binary_op(|a, b| a + b)?,
+                },
                 OpCode::Subtract => self.binary_op(|a, b| a - b)?,
                 OpCode::Multiply => self.binary_op(|a, b| a * b)?,
                 OpCode::Divide => self.binary_op(|a, b| a / b)?,
@@ -115,69 +668,396 @@ impl<'a> VM<'a> {
                     let value = self.pop();
                     self.stack.push(Value::Boolean(value.is_falsy()))
                 }
-                OpCode::Negate => {
-                    if self.peek(0).is_number() {
-                        let value = self.pop();
-                        self.stack.push(-value);
-                    } else {
-                        return self.runtime_error("Operand must be a number");
+                OpCode::Negate => match -self.peek(0) {
+                    Ok(value) => {
+                        self.pop();
+                        self.stack.push(value);
+                    }
+                    Err(message) => return self.runtime_error(&message),
+                },
+            }
+        }
+    }
+
+    fn trace_instruction(&mut self) -> io::Result<()> {
+        write!(self.trace, "           ")?;
+        for slot in &self.stack {
+            write!(self.trace, "[ {slot} ]")?;
+        }
+        writeln!(self.trace)?;
+        let frame = self.frames.last().unwrap();
+        frame
+            .closure
+            .function
+            .chunk
+            .disassemble_instruction(&mut *self.trace, frame.ip)?;
+        Ok(())
+    }
+
+    fn frame(&self) -> &CallFrame {
+        self.frames.last().unwrap()
+    }
+
+    fn frame_mut(&mut self) -> &mut CallFrame {
+        self.frames.last_mut().unwrap()
+    }
+
+    fn call_value(&mut self, callee: Value, arg_count: usize) -> Result<(), LoxError> {
+        if let Value::Obj(object) = callee {
+            match object {
+                Object::BoundMethod(bound) => {
+                    let slot = self.stack.len() - arg_count - 1;
+                    self.stack[slot] = bound.receiver;
+                    return self.call(bound.method, arg_count);
+                }
+                Object::Class(class) => {
+                    let slot = self.stack.len() - arg_count - 1;
+                    let instance = self.alloc(RefCell::new(Instance::new(class)));
+                    self.stack[slot] = Value::Obj(Object::Instance(instance));
+
+                    let initializer = class.borrow().methods.get(&self.init_string).copied();
+                    if let Some(initializer) = initializer {
+                        return self.call(initializer, arg_count);
+                    } else if arg_count != 0 {
+                        return self.runtime_error(&format!(
+                            "Expected 0 arguments but got {}.",
+                            arg_count
+                        ));
+                    }
+                    return Ok(());
+                }
+                Object::Closure(closure) => return self.call(closure, arg_count),
+                Object::Native(native) => {
+                    if arg_count != native.arity {
+                        return self.runtime_error(&format!(
+                            "Expected {} arguments but got {}.",
+                            native.arity, arg_count
+                        ));
+                    }
+
+                    let args = self.stack.len() - arg_count;
+                    let values: Vec<LoxValue> = self.stack[args..]
+                        .iter()
+                        .map(|arg| arg.to_lox_value())
+                        .collect();
+                    let result = (native.function)(&values);
+                    match result.and_then(|result| self.import(result)) {
+                        Ok(result) => {
+                            self.stack.truncate(args - 1);
+                            self.stack.push(result);
+                            return Ok(());
+                        }
+                        Err(message) => return self.runtime_error(&message),
                     }
                 }
+                _ => {}
+            }
+        }
+        self.runtime_error("Can only call functions and classes.")
+    }
+
+    fn invoke(&mut self, name: Gc<LoxString>, arg_count: usize) -> Result<(), LoxError> {
+        let instance = match self.peek(arg_count) {
+            Value::Obj(Object::Instance(instance)) => instance,
+            _ => return self.runtime_error("Only instances have methods."),
+        };
+
+        let field = instance.borrow().fields.get(&name).copied();
+        if let Some(value) = field {
+            let slot = self.stack.len() - arg_count - 1;
+            self.stack[slot] = value;
+            return self.call_value(value, arg_count);
+        }
+
+        let class = instance.borrow().class;
+        self.invoke_from_class(class, name, arg_count)
+    }
+
+    fn invoke_from_class(
+        &mut self,
+        class: Gc<RefCell<Class>>,
+        name: Gc<LoxString>,
+        arg_count: usize,
+    ) -> Result<(), LoxError> {
+        let method = class.borrow().methods.get(&name).copied();
+        match method {
+            Some(method) => self.call(method, arg_count),
+            None => self.runtime_error(&format!("Undefined property '{}'.", name)),
+        }
+    }
+
+    fn bind_method(
+        &mut self,
+        class: Gc<RefCell<Class>>,
+        name: Gc<LoxString>,
+    ) -> Result<(), LoxError> {
+        let method = class.borrow().methods.get(&name).copied();
+        match method {
+            Some(method) => {
+                let receiver = self.peek(0);
+                let bound = self.alloc(BoundMethod::new(receiver, method));
+                self.pop();
+                self.stack.push(Value::Obj(Object::BoundMethod(bound)));
+                Ok(())
             }
+            None => self.runtime_error(&format!("Undefined property '{}'.", name)),
         }
     }
 
+    fn define_method(&mut self, name: Gc<LoxString>) -> Result<(), LoxError> {
+        let method = self.peek(0);
+        if let (Value::Obj(Object::Closure(method)), Value::Obj(Object::Class(class))) =
+            (method, self.peek(1))
+        {
+            class.borrow_mut().methods.insert(name, method);
+            self.pop();
+            Ok(())
+        } else {
+            self.runtime_error("Unable to define method.")
+        }
+    }
+
+    fn call(&mut self, closure: Gc<Closure>, arg_count: usize) -> Result<(), LoxError> {
+        if arg_count != closure.function.arity {
+            return self.runtime_error(&format!(
+                "Expected {} arguments but got {}.",
+                closure.function.arity, arg_count
+            ));
+        }
+
+        if self.frames.len() == FRAMES_MAX {
+            return self.runtime_error("Stack overflow.");
+        }
+
+        self.frames.push(CallFrame {
+            closure,
+            ip: 0,
+            slots: self.stack.len() - arg_count - 1,
+        });
+        Ok(())
+    }
+
+    fn capture_upvalue(&mut self, slot: usize) -> Gc<RefCell<Upvalue>> {
+        for upvalue in &self.open_upvalues {
+            if let Upvalue::Open(index) = *upvalue.borrow() {
+                if index == slot {
+                    return *upvalue;
+                }
+            }
+        }
+
+        let upvalue = self.alloc(RefCell::new(Upvalue::Open(slot)));
+        self.open_upvalues.push(upvalue);
+        upvalue
+    }
+
+    fn close_upvalues(&mut self, last: usize) {
+        let stack = &self.stack;
+        self.open_upvalues.retain(|upvalue| {
+            let mut upvalue = upvalue.borrow_mut();
+            match *upvalue {
+                Upvalue::Open(index) if index >= last => {
+                    *upvalue = Upvalue::Closed(stack[index]);
+                    false
+                }
+                _ => true,
+            }
+        });
+    }
+
     fn pop(&mut self) -> Value {
         self.stack.pop().unwrap()
     }
 
     fn peek(&mut self, distance: usize) -> Value {
         if let Some(value) = self.stack.get(self.stack.len() - distance - 1) {
-            value.clone()
+            *value
         } else {
-            self.runtime_error("Get value failed.")
+            self.runtime_error::<(), _>("Get value failed.")
                 .expect("Get value failed.");
             Value::Nil
         }
     }
 
+    fn chunk(&self) -> &Chunk {
+        &self.frame().closure.function.chunk
+    }
+
     fn read_byte(&mut self) -> OpCode {
-        let val = self.chunk.read(self.ip).into();
-        self.ip += 1;
+        self.read_operand().into()
+    }
+
+    fn read_operand(&mut self) -> u8 {
+        let frame = self.frame_mut();
+        let val = frame.closure.function.chunk.read(frame.ip);
+        frame.ip += 1;
         val
     }
 
-    fn read_constant(&mut self) -> Result<Value, InterpretResult> {
-        let index = self.chunk.read(self.ip) as usize;
-        self.ip += 1;
-        self.chunk.get_constant(index)
+    fn read_short(&mut self) -> u16 {
+        let high = self.read_operand() as u16;
+        let low = self.read_operand() as u16;
+        (high << 8) | low
     }
 
-    fn binary_op(&mut self, op: fn(a: Value, b: Value) -> Value) -> Result<(), InterpretResult> {
-        if (self.peek(0).is_number() && self.peek(1).is_number())
-            || (self.peek(0).is_string() && self.peek(1).is_string())
-            || (self.peek(0).is_number() && self.peek(1).is_string())
-            || (self.peek(0).is_string() && self.peek(1).is_number())
-        {
-            let b = self.pop();
-            let a = self.pop();
-            self.stack.push(op(a, b));
-            return Ok(());
+    /// Reads a constant index operand, three bytes wide for the long forms.
+    fn read_index(&mut self, long: bool) -> usize {
+        if long {
+            let high = self.read_operand() as usize;
+            let low = self.read_short() as usize;
+            (high << 16) | low
+        } else {
+            self.read_operand() as usize
+        }
+    }
+
+    fn read_constant(&mut self, long: bool) -> Result<Value, LoxError> {
+        let index = self.read_index(long);
+        match self.chunk().get_constant(index) {
+            Some(value) => Ok(value),
+            None => self.runtime_error("Can`t read constant from table."),
+        }
+    }
+
+    fn read_string(&mut self, long: bool) -> Result<Gc<LoxString>, LoxError> {
+        match self.read_constant(long)? {
+            Value::Obj(Object::Str(s)) => Ok(s),
+            _ => self.runtime_error("Can`t read constant from table."),
         }
-        self.runtime_error("Operands must be number or string.")
     }
 
-    fn runtime_error<T: ToString + ?Sized>(&mut self, msg: &T) -> Result<(), InterpretResult> {
-        let line = self.chunk.get_line(self.ip - 1);
-        eprintln!("{}", msg.to_string());
-        eprintln!("[line {line}] in script.");
+    fn concatenate(&mut self) -> Result<(), LoxError> {
+        let result = match (self.peek(1), self.peek(0)) {
+            (Value::Obj(Object::Str(a)), Value::Obj(Object::Str(b))) => format!("{}{}", *a, *b),
+            (Value::Number(a), Value::Obj(Object::Str(b))) => format!("{}{}", a, *b),
+            (Value::Obj(Object::Str(a)), Value::Number(b)) => format!("{}{}", *a, b),
+            _ => return self.runtime_error("Operands must be numbers or strings."),
+        };
+        let result = self.intern(result);
+        self.pop();
+        self.pop();
+        self.stack.push(Value::Obj(Object::Str(result)));
+        Ok(())
+    }
+
+    fn alloc<T: Trace + 'static>(&mut self, value: T) -> Gc<T> {
+        if self.heap.should_collect() {
+            self.collect_garbage();
+        }
+        self.heap.alloc(value)
+    }
+
+    fn intern(&mut self, chars: String) -> Gc<LoxString> {
+        if self.heap.should_collect() {
+            self.collect_garbage();
+        }
+        self.heap.intern(chars)
+    }
+
+    fn collect_garbage(&mut self) {
+        self.mark_roots();
+        self.heap.trace_references();
+        self.heap.sweep();
+    }
+
+    fn mark_roots(&mut self) {
+        for value in &self.stack {
+            self.heap.mark_value(value);
+        }
+        for frame in &self.frames {
+            self.heap.mark(frame.closure);
+        }
+        for upvalue in &self.open_upvalues {
+            self.heap.mark(*upvalue);
+        }
+        for (name, value) in &self.globals {
+            self.heap.mark(*name);
+            self.heap.mark_value(value);
+        }
+        self.heap.mark(self.init_string);
+    }
+
+    fn binary_op(
+        &mut self,
+        op: fn(a: Value, b: Value) -> Result<Value, String>,
+    ) -> Result<(), LoxError> {
+        let b = self.peek(0);
+        let a = self.peek(1);
+        match op(a, b) {
+            Ok(value) => {
+                self.pop();
+                self.pop();
+                self.stack.push(value);
+                Ok(())
+            }
+            Err(message) => self.runtime_error(&message),
+        }
+    }
+
+    /// Reports a global read or written by the instruction at `offset` as
+    /// undefined, suggesting a similarly spelled local or global.
+    fn undefined_variable<T>(&mut self, name: Gc<LoxString>, offset: usize) -> Result<T, LoxError> {
+        let suggestion = match self.chunk().get_suggestion(offset) {
+            Some(local) => Some(local),
+            None => closest(
+                name.as_str(),
+                self.globals.keys().map(|global| global.as_str()),
+            ),
+        };
+        let help = suggestion.map(|suggestion| format!("did you mean '{suggestion}'?"));
+        self.error_with_help(&format!("Undefined variable '{}'.", *name), help)
+    }
+
+    fn runtime_error<T, M: ToString + ?Sized>(&mut self, msg: &M) -> Result<T, LoxError> {
+        self.error_with_help(msg, None)
+    }
+
+    fn error_with_help<T, M: ToString + ?Sized>(
+        &mut self,
+        msg: &M,
+        help: Option<String>,
+    ) -> Result<T, LoxError> {
+        let stack_trace: Vec<StackFrame> = self
+            .frames
+            .iter()
+            .rev()
+            .map(|frame| {
+                let function = &frame.closure.function;
+                let span = function.chunk.get_span(frame.ip - 1);
+                StackFrame {
+                    function: function.name.clone(),
+                    line: span.line,
+                    column: span.column,
+                }
+            })
+            .collect();
+        let chunk = &self.frame().closure.function.chunk;
+        let span = chunk.get_span(self.frame().ip - 1);
+        let diagnostic = Diagnostic {
+            message: msg.to_string(),
+            line: span.line,
+            column: span.column,
+            span: span.start..span.end,
+            source_line: source_line(chunk.source(), span.start),
+            lexeme: None,
+            help,
+            stack_trace,
+            incomplete: false,
+        };
         self.reset_stack();
-        Err(InterpretResult::RuntimeError)
+        Err(LoxError::new(ErrorKind::Runtime, vec![diagnostic]))
+    }
+}
+
+impl Default for Vm {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
-#[derive(Debug, Copy, Clone)]
-pub enum InterpretResult {
-    CompileError,
-    RuntimeError,
+fn clock_native(_args: &[LoxValue]) -> Result<LoxValue, String> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|e| e.to_string())?;
+    Ok(LoxValue::Number(now.as_secs_f64()))
 }
@@ -0,0 +1,109 @@
+//! Runs the `bytecode-lox` binary the way shell pipelines call it.
+
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+fn lox(args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_bytecode-lox"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[test]
+fn runs_inline_code() {
+    let output = lox(&["-e", "print 1 + 2;"], "");
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "3\n");
+}
+
+#[test]
+fn reads_the_script_from_stdin() {
+    let output = lox(&["-"], "print \"piped\";");
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "piped\n");
+}
+
+#[test]
+fn runs_a_script_file() {
+    let path = std::env::temp_dir().join(format!("cli-test-{}.lox", std::process::id()));
+    std::fs::write(&path, "print \"from file\";").unwrap();
+    let output = lox(&[path.to_str().unwrap()], "");
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "from file\n");
+}
+
+#[test]
+fn passes_remaining_arguments_to_the_script() {
+    let source = "print args.count; print args.get(0) + args.get(1);";
+    let output = lox(&["-e", source, "first", "--check"], "");
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "2\nfirst--check\n");
+}
+
+#[test]
+fn check_compiles_without_running() {
+    let output = lox(&["--check", "-e", "print \"ran\";"], "");
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "");
+
+    let output = lox(&["--check", "-e", "print ;"], "");
+    assert_eq!(output.status.code(), Some(65));
+    assert!(stderr(&output).starts_with("[line 1] Error at ';': Expected expression."));
+}
+
+#[test]
+fn exit_codes_tell_errors_apart() {
+    assert_eq!(lox(&["-e", "print ;"], "").status.code(), Some(65));
+    assert_eq!(lox(&["-e", "print -nil;"], "").status.code(), Some(66));
+    assert_eq!(lox(&["/no/such/script.lox"], "").status.code(), Some(74));
+    assert_eq!(lox(&["--bogus"], "").status.code(), Some(64));
+    assert_eq!(lox(&["-e"], "").status.code(), Some(64));
+}
+
+#[test]
+fn prints_version_and_help() {
+    let output = lox(&["--version"], "");
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+        stdout(&output),
+        format!("bytecode-lox {}\n", env!("CARGO_PKG_VERSION"))
+    );
+
+    let output = lox(&["--help"], "");
+    assert_eq!(output.status.code(), Some(0));
+    assert!(stdout(&output).starts_with("Usage: bytecode-lox [options]"));
+    assert!(stdout(&output).contains("--check"));
+}
+
+#[test]
+fn error_and_warning_options_are_applied() {
+    let errors = "print ;\n".repeat(3);
+    let output = lox(&["--max-errors", "1", "-e", &errors], "");
+    assert_eq!(stderr(&output).matches("Expected expression.").count(), 1);
+    let output = lox(&["--max-errors", "0", "-e", &errors], "");
+    assert_eq!(stderr(&output).matches("Expected expression.").count(), 3);
+
+    let unused = "fun f() { var x; }";
+    assert!(stderr(&lox(&["-e", unused], "")).contains("Warning (unused-variable)"));
+    assert_eq!(stderr(&lox(&["--no-warn", "all", "-e", unused], "")), "");
+}
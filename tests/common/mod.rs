@@ -0,0 +1,45 @@
+// Each test crate uses only some of these helpers.
+#![allow(dead_code)]
+
+use bytecode_lox::{LoxError, Vm};
+use std::cell::RefCell;
+use std::io;
+use std::io::Write;
+use std::rc::Rc;
+
+/// A sink that can still be read after a boxed clone is handed to the VM.
+#[derive(Clone, Default)]
+pub struct Capture(Rc<RefCell<Vec<u8>>>);
+
+impl Capture {
+    pub fn contents(&self) -> String {
+        String::from_utf8(self.0.borrow().clone()).unwrap()
+    }
+}
+
+impl Write for Capture {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A VM whose printed output and error reports are captured.
+pub fn vm() -> (Vm, Capture, Capture) {
+    let output = Capture::default();
+    let diagnostics = Capture::default();
+    let mut vm = Vm::new();
+    vm.set_output(Box::new(output.clone()));
+    vm.set_diagnostics(Box::new(diagnostics.clone()));
+    (vm, output, diagnostics)
+}
+
+/// Runs `source` in a fresh VM and returns what it printed.
+pub fn run(source: &str) -> Result<String, LoxError> {
+    let (mut vm, output, _) = vm();
+    vm.interpret(source)?;
+    Ok(output.contents())
+}
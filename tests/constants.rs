@@ -0,0 +1,60 @@
+//! Chunks with more constants than a one byte operand can index.
+
+mod common;
+
+/// A distinct identifier for each `i`, as identifiers can't contain digits.
+fn name(i: usize) -> String {
+    let letter = |n: usize| char::from(b'a' + (n % 26) as u8);
+    format!("g{}{}", letter(i / 26), letter(i))
+}
+
+#[test]
+fn constants_beyond_the_first_256_are_loaded() {
+    let numbers: Vec<String> = (0..400).map(|i| format!("{i}.5")).collect();
+    let source = format!("print {};", numbers.join(" + "));
+    let expected: f64 = (0..400).map(|i| i as f64 + 0.5).sum();
+    assert_eq!(common::run(&source).unwrap(), format!("{expected}\n"));
+}
+
+#[test]
+fn globals_beyond_the_first_256_constants_are_defined_read_and_set() {
+    let mut source = String::new();
+    for i in 0..300 {
+        source.push_str(&format!("var {} = \"v{i}\";\n", name(i)));
+    }
+    let last = name(299);
+    source.push_str(&format!(
+        "{last} = {last} + \"!\";\nprint {};\nprint {last};\n",
+        name(0)
+    ));
+    assert_eq!(common::run(&source).unwrap(), "v0\nv299!\n");
+}
+
+#[test]
+fn properties_and_methods_beyond_the_first_256_constants_work() {
+    let mut source = String::new();
+    for i in 0..300 {
+        source.push_str(&format!("var {} = {i};\n", name(i)));
+    }
+    source.push_str(
+        "class Box { get() { return this.value; } }\n\
+         var box = Box();\n\
+         box.value = \"boxed\";\n\
+         print box.get();\n",
+    );
+    assert_eq!(common::run(&source).unwrap(), "boxed\n");
+}
+
+#[test]
+fn long_constants_are_disassembled() {
+    let (mut vm, _, _) = common::vm();
+    let trace = common::Capture::default();
+    vm.set_trace(Box::new(trace.clone()));
+    vm.set_print_code(true);
+    let numbers: Vec<String> = (0..300).map(|i| i.to_string()).collect();
+    vm.interpret(&format!("print {};", numbers.join(" + ")))
+        .unwrap();
+    let listing = trace.contents();
+    assert!(listing.contains("OP_CONSTANT       255 '255'"));
+    assert!(listing.contains("OP_CONSTANT_LONG  256 '256'"));
+}
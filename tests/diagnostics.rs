@@ -0,0 +1,139 @@
+mod common;
+
+use bytecode_lox::{ErrorKind, StackFrame};
+
+#[test]
+fn compile_errors_are_reported_at_the_token() {
+    let (mut vm, output, diagnostics) = common::vm();
+    let error = vm
+        .interpret("var a = 1;\nprint a +;\nprint a;")
+        .unwrap_err();
+
+    assert_eq!(output.contents(), "");
+    assert_eq!(error.kind, ErrorKind::Compile);
+    let diagnostic = &error.diagnostics[0];
+    assert_eq!(diagnostic.message, "Expected expression.");
+    assert_eq!(diagnostic.line, 2);
+    assert_eq!(diagnostic.column, 10);
+    assert_eq!(diagnostic.lexeme.as_deref(), Some(";"));
+    assert!(diagnostic.stack_trace.is_empty());
+    assert_eq!(
+        diagnostics.contents(),
+        "[line 2] Error at ';': Expected expression.\n2 | print a +;\n  |          ^\n"
+    );
+}
+
+#[test]
+fn every_compile_error_is_returned() {
+    let (mut vm, _, _) = common::vm();
+    let error = vm
+        .interpret("print 1 +;\nvar = 2;\nprint \"open")
+        .unwrap_err();
+    let found: Vec<_> = error
+        .diagnostics
+        .iter()
+        .map(|diagnostic| {
+            (
+                diagnostic.line,
+                diagnostic.message.as_str(),
+                diagnostic.lexeme.as_deref(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        found,
+        [
+            (1, "Expected expression.", Some(";")),
+            (2, "Expect variable name.", Some("=")),
+            (3, "Unterminated string.", None),
+        ]
+    );
+    assert_eq!(
+        error.to_string(),
+        "[line 1] Expected expression.\n[line 2] Expect variable name.\n[line 3] Unterminated string."
+    );
+}
+
+#[test]
+fn runtime_errors_are_returned_without_running_further() {
+    let (mut vm, output, _) = common::vm();
+    let error = vm
+        .interpret("print 1;\nprint -\"a\";\nprint 2;")
+        .unwrap_err();
+    assert_eq!(output.contents(), "1\n");
+    assert_eq!(error.kind, ErrorKind::Runtime);
+    assert_eq!(error.diagnostics.len(), 1);
+    assert_eq!(error.diagnostics[0].message, "Operand must be a number.");
+    assert_eq!(error.diagnostics[0].line, 2);
+    assert_eq!(error.diagnostics[0].lexeme, None);
+}
+
+#[test]
+fn runtime_errors_carry_location_and_stack_trace() {
+    let (mut vm, output, diagnostics) = common::vm();
+    let error = vm
+        .interpret("print \"before\";\nfun f(x) {\n  return x + nil;\n}\nf(1);\n")
+        .unwrap_err();
+
+    assert_eq!(output.contents(), "before\n");
+    assert_eq!(error.kind, ErrorKind::Runtime);
+    assert_eq!(error.diagnostics.len(), 1);
+    let diagnostic = &error.diagnostics[0];
+    assert_eq!(diagnostic.message, "Operands must be numbers or strings.");
+    assert_eq!(diagnostic.line, 3);
+    assert_eq!(diagnostic.column, 12);
+    assert_eq!(diagnostic.source_line, "  return x + nil;");
+    assert_eq!(
+        diagnostic.stack_trace,
+        vec![
+            StackFrame {
+                function: Some("f".to_string()),
+                line: 3,
+                column: 12,
+            },
+            StackFrame {
+                function: None,
+                line: 5,
+                column: 4,
+            },
+        ]
+    );
+    assert!(diagnostics.contents().starts_with(&diagnostic.message));
+}
+
+#[test]
+fn stack_traces_name_methods_and_closures_innermost_first() {
+    let (mut vm, _, diagnostics) = common::vm();
+    let source = "\
+class Parser {
+  parse(text) {
+    fun fail() {
+      return text.length;
+    }
+    return fail();
+  }
+}
+fun run() {
+  return Parser().parse(\"x\");
+}
+run();
+";
+    let error = vm.interpret(source).unwrap_err();
+    let frames: Vec<(Option<&str>, usize)> = error.diagnostics[0]
+        .stack_trace
+        .iter()
+        .map(|frame| (frame.function.as_deref(), frame.line))
+        .collect();
+    assert_eq!(
+        frames,
+        [
+            (Some("fail"), 4),
+            (Some("parse"), 6),
+            (Some("run"), 10),
+            (None, 12),
+        ]
+    );
+    assert!(diagnostics.contents().ends_with(
+        "[line 4] in fail().\n[line 6] in parse().\n[line 10] in run().\n[line 12] in script.\n"
+    ));
+}
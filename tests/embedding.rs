@@ -0,0 +1,74 @@
+mod common;
+
+use bytecode_lox::{ErrorKind, LoxValue, Vm};
+
+#[test]
+fn evaluated_values_outlive_later_collections() {
+    let (mut vm, _, _) = common::vm();
+    let value = vm.evaluate("\"con\" + \"cat\"").unwrap();
+    vm.interpret("var s = \"\"; for (var i = 0; i < 10; i = i + 1) s = s + \"x\";")
+        .unwrap();
+    assert_eq!(value, LoxValue::String("concat".to_string()));
+    assert_eq!(vm.get_global("s"), Some(LoxValue::String("x".repeat(10))));
+}
+
+#[test]
+fn natives_exchange_owned_values() {
+    let (mut vm, output, _) = common::vm();
+    vm.define_native("shout", 1, |args| {
+        let text = String::try_from(args[0].clone())?;
+        Ok(LoxValue::String(text.to_uppercase()))
+    });
+    vm.set_global("name", "lox").unwrap();
+    vm.interpret("print shout(\"hi \" + name);").unwrap();
+    assert_eq!(output.contents(), "HI LOX\n");
+
+    vm.interpret("fun f() {}").unwrap();
+    let function = vm.get_global("f").unwrap();
+    assert!(vm.set_global("g", function).is_err());
+}
+
+#[test]
+fn globals_are_copied_in_and_out() {
+    let (mut vm, output, _) = common::vm();
+    vm.set_global("count", 2.0).unwrap();
+    vm.set_global("enabled", true).unwrap();
+    vm.set_global("nothing", ()).unwrap();
+    vm.interpret("var doubled = count * 2; print enabled; print nothing;")
+        .unwrap();
+
+    assert_eq!(output.contents(), "true\nnil\n");
+    assert_eq!(vm.get_global("doubled"), Some(LoxValue::Number(4.0)));
+    assert_eq!(vm.get_global("missing"), None);
+    let mut names: Vec<&str> = vm.globals().map(|(name, _)| name).collect();
+    names.sort();
+    assert_eq!(names, ["clock", "count", "doubled", "enabled", "nothing"]);
+}
+
+#[test]
+fn objects_come_out_as_their_printed_form() {
+    let (mut vm, _, _) = common::vm();
+    vm.interpret("class Point {} var p = Point();").unwrap();
+    let point = vm.get_global("p").unwrap();
+    assert_eq!(point, LoxValue::Object("Point instance".to_string()));
+    assert!(f64::try_from(point).is_err());
+}
+
+#[test]
+fn errors_from_natives_are_runtime_errors() {
+    let (mut vm, _, _) = common::vm();
+    vm.define_native("fail", 0, |_| Err("Native failed.".to_string()));
+    let error = vm.interpret("fail();").unwrap_err();
+    assert_eq!(error.kind, ErrorKind::Runtime);
+    assert_eq!(error.diagnostics[0].message, "Native failed.");
+}
+
+#[test]
+fn values_outlive_the_vm() {
+    let value = {
+        let mut vm = Vm::new();
+        vm.interpret("var s = \"hello \" + \"world\";").unwrap();
+        vm.get_global("s").unwrap()
+    };
+    assert_eq!(String::try_from(value).unwrap(), "hello world");
+}
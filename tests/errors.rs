@@ -0,0 +1,33 @@
+//! Bad scripts must come back as errors rather than crash the host.
+
+mod common;
+
+use bytecode_lox::ErrorKind;
+
+fn error_kind(source: &str) -> ErrorKind {
+    let (mut vm, _, _) = common::vm();
+    vm.interpret(source).unwrap_err().kind
+}
+
+#[test]
+fn source_ending_mid_token_is_a_compile_error() {
+    assert_eq!(error_kind("1 /"), ErrorKind::Compile);
+    assert_eq!(error_kind("print 1."), ErrorKind::Compile);
+}
+
+#[test]
+fn arithmetic_on_wrong_types_is_a_runtime_error() {
+    assert_eq!(error_kind("print \"a\" - 1;"), ErrorKind::Runtime);
+    assert_eq!(error_kind("print \"a\" * 2;"), ErrorKind::Runtime);
+    assert_eq!(error_kind("print -\"a\";"), ErrorKind::Runtime);
+    assert_eq!(error_kind("print 1 + nil;"), ErrorKind::Runtime);
+}
+
+#[test]
+fn division_by_zero_is_a_runtime_error() {
+    let (mut vm, _, _) = common::vm();
+    let error = vm.interpret("print 1 / 0;").unwrap_err();
+    assert_eq!(error.kind, ErrorKind::Runtime);
+    assert_eq!(error.diagnostics[0].message, "Can't divide by zero.");
+    assert_eq!(error.diagnostics[0].line, 1);
+}
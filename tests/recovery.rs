@@ -0,0 +1,69 @@
+//! The compiler reports every error in a script in one pass.
+
+mod common;
+
+use bytecode_lox::LoxError;
+
+fn compile_errors(vm: &mut bytecode_lox::Vm, source: &str) -> Vec<(usize, String)> {
+    let error: LoxError = vm.check(source).unwrap_err();
+    error
+        .diagnostics
+        .into_iter()
+        .map(|diagnostic| (diagnostic.line, diagnostic.message))
+        .collect()
+}
+
+#[test]
+fn errors_in_separate_statements_are_all_reported() {
+    let (mut vm, _, _) = common::vm();
+    let errors = compile_errors(&mut vm, "var = 1;\nprint 2;\nprint (;\nvar x = 3");
+    assert_eq!(
+        errors,
+        vec![
+            (1, "Expect variable name.".to_string()),
+            (3, "Expected expression.".to_string()),
+            (4, "Expect ';' after variable declaration.".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn recovery_stays_inside_blocks_and_parentheses() {
+    let (mut vm, _, _) = common::vm();
+    let source = "fun f() {\n  print 1 +;\n  print 2 +;\n}\nprint (1 + ) * 2;\nprint ;\n";
+    let lines: Vec<usize> = compile_errors(&mut vm, source)
+        .into_iter()
+        .map(|(line, _)| line)
+        .collect();
+    assert_eq!(lines, vec![2, 3, 5, 6]);
+}
+
+#[test]
+fn error_cap_stops_compiling_after_that_many_errors() {
+    let source = "print ;\n".repeat(5);
+    let (mut vm, _, _) = common::vm();
+    assert_eq!(compile_errors(&mut vm, &source).len(), 5);
+
+    vm.set_max_errors(Some(2));
+    let errors = compile_errors(&mut vm, &source);
+    assert_eq!(errors.len(), 3);
+    assert_eq!(
+        errors[2],
+        (3, "Too many errors; stopping after 2.".to_string())
+    );
+
+    vm.set_max_errors(Some(0));
+    let errors = compile_errors(&mut vm, &source);
+    assert_eq!(errors.len(), 2);
+    assert_eq!(
+        errors[1],
+        (2, "Too many errors; stopping after 1.".to_string())
+    );
+
+    // Reaching the limit at the very end leaves nothing unreported.
+    vm.set_max_errors(Some(5));
+    assert_eq!(compile_errors(&mut vm, &source).len(), 5);
+
+    vm.set_max_errors(None);
+    assert_eq!(compile_errors(&mut vm, &source).len(), 5);
+}
@@ -0,0 +1,83 @@
+//! The interactive REPL, driven through the binary's standard input.
+
+use bytecode_lox::Vm;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+/// A fresh home directory for the history dotfile.
+fn home(name: &str) -> PathBuf {
+    let home = std::env::temp_dir().join(format!("repl-test-{name}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&home);
+    std::fs::create_dir_all(&home).unwrap();
+    home
+}
+
+fn repl(home: &PathBuf, input: &str) -> String {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_bytecode-lox"))
+        .env("HOME", home)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+#[test]
+fn history_file_is_trimmed_to_the_limit() {
+    let home = home("history");
+    let path = home.join(".bytecode_lox_history");
+    let old: String = (0..1500).map(|i| format!("print {i};\n")).collect();
+    std::fs::write(&path, old).unwrap();
+
+    repl(&home, "print \"new\";\n");
+    let history = std::fs::read_to_string(&path).unwrap();
+    let lines: Vec<&str> = history.lines().collect();
+    assert_eq!(lines.len(), 1001);
+    assert_eq!(lines[0], "print 500;");
+    assert_eq!(lines[1000], "print \"new\";");
+    std::fs::remove_dir_all(&home).unwrap();
+}
+
+#[test]
+fn input_is_incomplete_only_when_more_lines_could_finish_it() {
+    let mut vm = Vm::new();
+    assert!(vm.is_incomplete("fun f() {\n  print 1;\n"));
+    assert!(vm.is_incomplete("print max(1,\n"));
+    assert!(vm.is_incomplete("print \"a long\nstring"));
+    assert!(!vm.is_incomplete("print 1;"));
+    assert!(!vm.is_incomplete("print 1 +;\nfun f() {"));
+    assert!(!vm.is_incomplete("print @"));
+}
+
+#[test]
+fn trailing_expressions_are_echoed() {
+    let home = home("echo");
+    let output = repl(
+        &home,
+        "var a = 2;\na * 3\nfun f() {\n  return \"multi\";\n}\nf()\nprint a;\n",
+    );
+    assert_eq!(output, "> > 6\n> . . > multi\n> 2\n> ");
+    std::fs::remove_dir_all(&home).unwrap();
+}
+
+#[test]
+fn commands_inspect_the_session() {
+    let home = home("commands");
+    let output = repl(
+        &home,
+        "var b = 1;\nfun f() { return 2; }\n:globals\n:disasm f\n:reset\n:globals\n",
+    );
+    assert!(output.contains("args = Args instance\nb = 1\nclock = <native fn>\nf = <fn f>\n"));
+    assert!(output.contains("== <fn f> ==\n"));
+    assert!(output.ends_with("> > args = Args instance\nclock = <native fn>\n> "));
+    std::fs::remove_dir_all(&home).unwrap();
+}
@@ -0,0 +1,112 @@
+//! Scripts that allocate heavily. Run them with `cargo test-stress` to have
+//! the garbage collector collect on every allocation.
+
+mod common;
+
+use common::run;
+
+#[test]
+fn closures_keep_captured_variables_alive() {
+    let output = run(r#"
+        fun counter(name) {
+          var count = 0;
+          fun increment() {
+            count = count + 1;
+            return name + " " + "is at";
+          }
+          fun get() { return count; }
+          fun both() { print increment(); print get(); }
+          return both;
+        }
+        var a = counter("a");
+        var b = counter("b");
+        a(); a(); b(); a();
+    "#);
+    assert_eq!(
+        output.unwrap(),
+        "a is at\n1\na is at\n2\nb is at\n1\na is at\n3\n"
+    );
+}
+
+#[test]
+fn upvalues_are_closed_over_per_iteration() {
+    let output = run(r#"
+        var closures = nil;
+        fun keep(f, rest) {
+          fun node(first) { if (first) return f; return rest; }
+          return node;
+        }
+        for (var i = 0; i < 3; i = i + 1) {
+          var label = "item " + "no";
+          var n = i;
+          fun show() { print label + "!"; print n; }
+          closures = keep(show, closures);
+        }
+        while (closures != nil) {
+          closures(true)();
+          closures = closures(false);
+        }
+    "#);
+    assert_eq!(output.unwrap(), "item no!\n2\nitem no!\n1\nitem no!\n0\n");
+}
+
+#[test]
+fn instances_hold_fields_and_bound_methods() {
+    let output = run(r#"
+        class Pair {
+          init(left, right) {
+            this.left = left;
+            this.right = right;
+          }
+          join(separator) { return this.left + separator + this.right; }
+        }
+        var pair = Pair("a" + "b", "c" + "d");
+        var join = pair.join;
+        pair.left = pair.left + "!";
+        print join(", ");
+        print Pair("x", "y").join("-");
+        print pair;
+    "#);
+    assert_eq!(output.unwrap(), "ab!, cd\nx-y\nPair instance\n");
+}
+
+#[test]
+fn subclasses_inherit_and_call_super_methods() {
+    let output = run(r#"
+        class Shape {
+          init(name) { this.name = name; }
+          describe() { return "a " + this.name; }
+        }
+        class Square < Shape {
+          init(side) {
+            super.init("square");
+            this.side = side;
+          }
+          describe() {
+            var base = super.describe;
+            return base() + " with area";
+          }
+          area() { return this.side * this.side; }
+        }
+        var square = Square(3);
+        print square.describe();
+        print square.area();
+        print Square;
+    "#);
+    assert_eq!(output.unwrap(), "a square with area\n9\nSquare\n");
+}
+
+#[test]
+fn concatenated_strings_survive_collections() {
+    let output = run(r#"
+        var text = "";
+        for (var i = 0; i < 50; i = i + 1) {
+          text = text + "ab";
+        }
+        var parts = "start" + "-" + text + "-" + "end";
+        print parts == "start-" + text + "-end";
+        print parts;
+    "#);
+    let expected = format!("true\nstart-{}-end\n", "ab".repeat(50));
+    assert_eq!(output.unwrap(), expected);
+}
@@ -0,0 +1,58 @@
+mod common;
+
+use bytecode_lox::Diagnostic;
+
+fn runtime_error(source: &str) -> Diagnostic {
+    let (mut vm, _, _) = common::vm();
+    let mut error = vm.interpret(source).unwrap_err();
+    error.diagnostics.remove(0)
+}
+
+#[test]
+fn assignments_point_at_their_target() {
+    let diagnostic = runtime_error("var x = 1;\n  undefinedName = \"some long value\";");
+    assert_eq!(diagnostic.line, 2);
+    assert_eq!(diagnostic.column, 3);
+    assert_eq!(diagnostic.span, 13..26);
+
+    let diagnostic = runtime_error("var a = nil; a.field = 1 + 2;");
+    assert_eq!(diagnostic.column, 16);
+    assert_eq!(diagnostic.span, 15..20);
+}
+
+#[test]
+fn operators_point_at_the_operator() {
+    let diagnostic = runtime_error("print 1 +\n  (2 - nil);");
+    assert_eq!(diagnostic.message, "Operands must be numbers.");
+    assert_eq!((diagnostic.line, diagnostic.column), (2, 6));
+    assert_eq!(diagnostic.source_line, "  (2 - nil);");
+}
+
+#[test]
+fn columns_count_characters_and_spans_count_bytes() {
+    let diagnostic = runtime_error("var é = \"ü\"; print é - 1;");
+    assert_eq!(diagnostic.column, 22);
+    assert_eq!(diagnostic.span, 24..25);
+
+    let (mut vm, _, _) = common::vm();
+    let error = vm.interpret("var s = \"ü\" +;").unwrap_err();
+    let diagnostic = &error.diagnostics[0];
+    assert_eq!(diagnostic.column, 14);
+    assert_eq!(diagnostic.span, 14..15);
+    assert_eq!(diagnostic.lexeme.as_deref(), Some(";"));
+}
+
+#[test]
+fn spans_are_exact_deep_into_long_chunks() {
+    let mut source = String::from("fun f(x) {\n  var s = \"two\nlines\";\n");
+    for _ in 0..300 {
+        source.push_str("  s = s + \"\";\n");
+    }
+    let padding = " ".repeat(200);
+    source.push_str(&format!("{padding}return x - \"a\";\n}}\nf(1);\n"));
+    let diagnostic = runtime_error(&source);
+    assert_eq!(diagnostic.line, 304);
+    assert_eq!(diagnostic.column, 210);
+    assert_eq!(&source[diagnostic.span.clone()], "-");
+    assert_eq!(diagnostic.stack_trace[1].line, 306);
+}
@@ -0,0 +1,44 @@
+mod common;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+#[test]
+fn print_writes_to_the_output_sink() {
+    let (mut vm, output, diagnostics) = common::vm();
+    vm.interpret("print 1 + 2; print \"two\";").unwrap();
+    assert_eq!(output.contents(), "3\ntwo\n");
+    assert_eq!(diagnostics.contents(), "");
+}
+
+#[test]
+fn errors_and_unhandled_warnings_go_to_the_diagnostics_sink() {
+    let (mut vm, output, diagnostics) = common::vm();
+    vm.interpret("fun f() { var unused; }").unwrap();
+    assert!(diagnostics
+        .contents()
+        .starts_with("[line 1] Warning (unused-variable): "));
+
+    let (mut vm, _, diagnostics) = common::vm();
+    vm.interpret("print missing;").unwrap_err();
+    assert!(diagnostics
+        .contents()
+        .starts_with("Undefined variable 'missing'."));
+    assert_eq!(output.contents(), "");
+}
+
+#[test]
+fn a_warning_handler_replaces_the_diagnostics_sink() {
+    let (mut vm, _, diagnostics) = common::vm();
+    let warnings = Rc::new(RefCell::new(Vec::new()));
+    let seen = Rc::clone(&warnings);
+    vm.set_warning_handler(Box::new(move |warning| {
+        seen.borrow_mut().push(warning.diagnostic.message.clone());
+    }));
+    vm.interpret("fun f() { var unused; }").unwrap();
+    assert_eq!(
+        *warnings.borrow(),
+        vec!["Local variable 'unused' is never read.".to_string()]
+    );
+    assert_eq!(diagnostics.contents(), "");
+}
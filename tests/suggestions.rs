@@ -0,0 +1,50 @@
+mod common;
+
+fn help(source: &str) -> Option<String> {
+    let (mut vm, _, _) = common::vm();
+    let mut error = vm.interpret(source).unwrap_err();
+    error.diagnostics.remove(0).help
+}
+
+#[test]
+fn suggests_globals_spelled_alike() {
+    assert_eq!(
+        help("var count = 1; print coutn;").as_deref(),
+        Some("did you mean 'count'?")
+    );
+    assert_eq!(
+        help("var abc = 1; abd = 2;").as_deref(),
+        Some("did you mean 'abc'?")
+    );
+    assert_eq!(
+        help("print clokc();").as_deref(),
+        Some("did you mean 'clock'?")
+    );
+}
+
+#[test]
+fn suggests_locals_in_scope_where_the_name_was_used() {
+    assert_eq!(
+        help("fun f() { var total = 1; print totl; } f();").as_deref(),
+        Some("did you mean 'total'?")
+    );
+    assert_eq!(
+        help("fun f() { var total = 1; fun g() { print totl; } g(); } f();").as_deref(),
+        Some("did you mean 'total'?")
+    );
+}
+
+#[test]
+fn suggests_nothing_for_unrelated_names() {
+    assert_eq!(help("var alpha = 1; print zzz;"), None);
+    assert_eq!(help("var a = 1; print b;"), None);
+}
+
+#[test]
+fn suggestions_are_rendered_as_help() {
+    let (mut vm, _, diagnostics) = common::vm();
+    vm.interpret("var count = 1; print coutn;").unwrap_err();
+    assert!(diagnostics
+        .contents()
+        .contains("  = help: did you mean 'count'?\n"));
+}
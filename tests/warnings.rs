@@ -0,0 +1,118 @@
+mod common;
+
+use bytecode_lox::{Vm, Warning, WarningKind};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// Compiles `source` and returns the kind, line and message of each warning.
+fn check(vm: &mut Vm, source: &str) -> Vec<(WarningKind, usize, String)> {
+    let warnings: Rc<RefCell<Vec<Warning>>> = Rc::default();
+    let seen = Rc::clone(&warnings);
+    vm.set_warning_handler(Box::new(move |warning| {
+        seen.borrow_mut().push(warning.clone())
+    }));
+    vm.check(source).unwrap();
+    let warnings = warnings.borrow();
+    warnings
+        .iter()
+        .map(|warning| {
+            let diagnostic = &warning.diagnostic;
+            (warning.kind, diagnostic.line, diagnostic.message.clone())
+        })
+        .collect()
+}
+
+fn warnings(source: &str) -> Vec<(WarningKind, usize, String)> {
+    check(&mut Vm::new(), source)
+}
+
+#[test]
+fn warns_about_unused_locals() {
+    assert_eq!(
+        warnings("fun f() {\n  var x = 1;\n}"),
+        [(
+            WarningKind::UnusedVariable,
+            2,
+            "Local variable 'x' is never read.".to_string()
+        )]
+    );
+    assert!(warnings("fun f() { var _x = 1; }").is_empty());
+    assert!(warnings("fun f(unused) {}").is_empty());
+    assert!(warnings("fun f() { var x = 1; print x; }").is_empty());
+}
+
+#[test]
+fn warns_about_code_after_return() {
+    assert_eq!(
+        warnings("fun f() {\n  return 1;\n  print 2;\n}"),
+        [(
+            WarningKind::UnreachableCode,
+            3,
+            "Unreachable code after 'return'.".to_string()
+        )]
+    );
+}
+
+#[test]
+fn warns_about_statements_without_effect() {
+    assert_eq!(
+        warnings("print 1;\n1 + 2;"),
+        [(
+            WarningKind::NoEffect,
+            2,
+            "Expression statement has no effect.".to_string()
+        )]
+    );
+    assert!(warnings("var a; a = 1; clock();").is_empty());
+}
+
+#[test]
+fn warns_about_shadowed_locals() {
+    assert_eq!(
+        warnings("fun f(a) {\n  {\n    var a = 1;\n    print a;\n  }\n}"),
+        [(
+            WarningKind::Shadowing,
+            3,
+            "'a' shadows a variable in an enclosing scope.".to_string()
+        )]
+    );
+}
+
+#[test]
+fn warnings_can_be_turned_off_one_kind_at_a_time() {
+    let source = "fun f(a) {\n  var other;\n  { var a = 1; print a; }\n  return;\n  1;\n}";
+    let mut vm = Vm::new();
+    let kinds = |found: Vec<(WarningKind, usize, String)>| -> Vec<WarningKind> {
+        found.into_iter().map(|(kind, _, _)| kind).collect()
+    };
+    assert_eq!(
+        kinds(check(&mut vm, source)),
+        [
+            WarningKind::UnusedVariable,
+            WarningKind::Shadowing,
+            WarningKind::UnreachableCode,
+            WarningKind::NoEffect,
+        ]
+    );
+
+    vm.set_warning(WarningKind::Shadowing, false);
+    assert_eq!(
+        kinds(check(&mut vm, source)),
+        [
+            WarningKind::UnusedVariable,
+            WarningKind::UnreachableCode,
+            WarningKind::NoEffect,
+        ]
+    );
+    for kind in WarningKind::ALL {
+        vm.set_warning(kind, false);
+    }
+    assert!(check(&mut vm, source).is_empty());
+}
+
+#[test]
+fn scripts_with_errors_report_no_warnings() {
+    let (mut vm, _, diagnostics) = common::vm();
+    vm.interpret("fun f() { var x; }\nprint ;").unwrap_err();
+    assert!(!diagnostics.contents().contains("Warning"));
+}